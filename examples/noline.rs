@@ -1,8 +1,12 @@
 extern crate menu;
 
 use embedded_io::{ErrorType, Read as EmbRead, Write as EmbWrite};
-use menu::*;
-use noline::builder::EditorBuilder;
+use menu::{
+    history::SkipSecrets,
+    newline::{Newline, Translate},
+    *,
+};
+use noline::{history::UnboundedHistory, line_buffer::LineBuffer, sync_editor::Editor};
 use std::io::{self, Read as _, Stdin, Stdout, Write as _};
 use termion::raw::IntoRawMode;
 
@@ -38,19 +42,7 @@ impl EmbRead for IOWrapper {
 
 impl EmbWrite for IOWrapper {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        let mut written = 0;
-        let parts = buf.split(|b| *b == b'\n').collect::<Vec<_>>();
-
-        for (i, part) in parts.iter().enumerate() {
-            written += self.stdout.write(part).map_err(|e| e.kind())?;
-
-            if i != parts.len() - 1 {
-                let _ = self.stdout.write(b"\r\n").map_err(|e| e.kind())?;
-                written += 1;
-            }
-        }
-
-        Ok(written)
+        Ok(self.stdout.write(buf).map_err(|e| e.kind())?)
     }
 
     fn flush(&mut self) -> Result<(), Self::Error> {
@@ -58,16 +50,20 @@ impl EmbWrite for IOWrapper {
     }
 }
 
+/// The terminal is in raw mode, so it needs `\r\n` at the end of each line
+type Io = Translate<IOWrapper>;
+
 #[derive(Default)]
 struct Context {
     _inner: u32,
 }
 
-const ROOT_MENU: Menu<IOWrapper, Context> = Menu {
-    label: "root",
-    items: &[
-        &Item {
-            item_type: ItemType::Callback {
+const ROOT_MENU: Menu<Io, Context> = Menu::new(
+    "root",
+    &[
+        &Item::new(
+            "foo",
+            ItemType::Callback {
                 function: select_foo,
                 parameters: &[
                     Parameter::Mandatory {
@@ -89,86 +85,90 @@ const ROOT_MENU: Menu<IOWrapper, Context> = Menu {
                     },
                 ],
             },
-            command: "foo",
-            help: Some(
-                "Makes a foo appear.
+        )
+        .help(
+            "Makes a foo appear.
 
 This is some extensive help text.
 
 It contains multiple paragraphs and should be preceeded by the parameter list.
 ",
-            ),
-        },
-        &Item {
-            item_type: ItemType::Callback {
+        ),
+        &Item::new(
+            "bar",
+            ItemType::Callback {
                 function: select_bar,
                 parameters: &[],
             },
-            command: "bar",
-            help: Some("fandoggles a bar"),
-        },
-        &Item {
-            item_type: ItemType::Menu(&Menu {
-                label: "sub",
-                items: &[
-                    &Item {
-                        item_type: ItemType::Callback {
-                            function: select_baz,
-                            parameters: &[],
-                        },
-                        command: "baz",
-                        help: Some("thingamobob a baz"),
-                    },
-                    &Item {
-                        item_type: ItemType::Callback {
-                            function: select_quux,
-                            parameters: &[],
-                        },
-                        command: "quux",
-                        help: Some("maximum quux"),
-                    },
-                ],
-                entry: Some(enter_sub),
-                exit: Some(exit_sub),
-            }),
-            command: "sub",
-            help: Some("enter sub-menu"),
-        },
+        )
+        .help("fandoggles a bar")
+        .aliases(&["fandoggle"]),
+        &Item::new(
+            "sub",
+            ItemType::Menu(
+                &Menu::new(
+                    "sub",
+                    &[
+                        &Item::new(
+                            "baz",
+                            ItemType::Callback {
+                                function: select_baz,
+                                parameters: &[],
+                            },
+                        )
+                        .help("thingamobob a baz"),
+                        &Item::new(
+                            "quux",
+                            ItemType::Callback {
+                                function: select_quux,
+                                parameters: &[],
+                            },
+                        )
+                        .help("maximum quux"),
+                    ],
+                )
+                .entry(enter_sub)
+                .exit(exit_sub),
+            ),
+        )
+        .help("enter sub-menu"),
     ],
-    entry: Some(enter_root),
-    exit: Some(exit_root),
-};
+)
+.entry(enter_root)
+.exit(exit_root);
 
 fn main() {
     let _stdout = io::stdout().into_raw_mode().unwrap();
 
-    let mut io = IOWrapper::new();
-    let mut editor = EditorBuilder::new_unbounded()
-        .with_unbounded_history()
-        .build_sync(&mut io)
-        .unwrap();
+    let mut io = Translate::new(IOWrapper::new(), Newline::CrLf);
+    // Keep secret arguments out of the history
+    let history = SkipSecrets::new(UnboundedHistory::new(), &ROOT_MENU);
+    let mut editor = Editor::new(LineBuffer::new_unbounded(), history, &mut io).unwrap();
 
     let mut context = Context::default();
     let mut r = Runner::new(ROOT_MENU, &mut editor, io, &mut context);
+    if let Ok((columns, _rows)) = termion::terminal_size() {
+        r.set_help_width(Some(columns.into()));
+    }
 
-    while let Ok(_) = r.input_line(&mut context) {}
+    while r.input_line(&mut context).is_ok() {}
 }
 
-fn enter_root(_menu: &Menu<IOWrapper, Context>, interface: &mut IOWrapper, _context: &mut Context) {
+fn enter_root(_menu: &Menu<Io, Context>, interface: &mut Io, _context: &mut Context) {
     writeln!(interface, "In enter_root").unwrap();
 }
 
-fn exit_root(_menu: &Menu<IOWrapper, Context>, interface: &mut IOWrapper, _context: &mut Context) {
+fn exit_root(_menu: &Menu<Io, Context>, interface: &mut Io, _context: &mut Context) {
     writeln!(interface, "In exit_root").unwrap();
 }
 
 fn select_foo(
-    _menu: &Menu<IOWrapper, Context>,
-    item: &Item<IOWrapper, Context>,
+    _menu: &Menu<Io, Context>,
+    item: &Item<Io, Context>,
     args: &[&str],
-    interface: &mut IOWrapper,
+    interface: &mut Io,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_foo. Args = {:?}", args).unwrap();
     writeln!(
         interface,
@@ -200,42 +200,46 @@ fn select_foo(
         ::menu::argument_finder(item, args, "no_such_arg")
     )
     .unwrap();
+    Status::Success
 }
 
 fn select_bar(
-    _menu: &Menu<IOWrapper, Context>,
-    _item: &Item<IOWrapper, Context>,
+    _menu: &Menu<Io, Context>,
+    _item: &Item<Io, Context>,
     args: &[&str],
-    interface: &mut IOWrapper,
+    interface: &mut Io,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_bar. Args = {:?}", args).unwrap();
+    Status::Success
 }
 
-fn enter_sub(_menu: &Menu<IOWrapper, Context>, interface: &mut IOWrapper, _context: &mut Context) {
+fn enter_sub(_menu: &Menu<Io, Context>, interface: &mut Io, _context: &mut Context) {
     writeln!(interface, "In enter_sub").unwrap();
 }
 
-fn exit_sub(_menu: &Menu<IOWrapper, Context>, interface: &mut IOWrapper, _context: &mut Context) {
+fn exit_sub(_menu: &Menu<Io, Context>, interface: &mut Io, _context: &mut Context) {
     writeln!(interface, "In exit_sub").unwrap();
 }
 
 fn select_baz(
-    _menu: &Menu<IOWrapper, Context>,
-    _item: &Item<IOWrapper, Context>,
+    _menu: &Menu<Io, Context>,
+    _item: &Item<Io, Context>,
     args: &[&str],
-    interface: &mut IOWrapper,
+    interface: &mut Io,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_baz: Args = {:?}", args).unwrap();
+    Status::Success
 }
 
 fn select_quux(
-    _menu: &Menu<IOWrapper, Context>,
-    _item: &Item<IOWrapper, Context>,
+    _menu: &Menu<Io, Context>,
+    _item: &Item<Io, Context>,
     args: &[&str],
-    interface: &mut IOWrapper,
+    interface: &mut Io,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_quux: Args = {:?}", args).unwrap();
+    Status::Success
 }
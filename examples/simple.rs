@@ -10,11 +10,12 @@ struct Context {
     _inner: u32,
 }
 
-const ROOT_MENU: Menu<Output, Context> = Menu {
-    label: "root",
-    items: &[
-        &Item {
-            item_type: ItemType::Callback {
+const ROOT_MENU: Menu<Output, Context> = Menu::new(
+    "root",
+    &[
+        &Item::new(
+            "foo",
+            ItemType::Callback {
                 function: select_foo,
                 parameters: &[
                     Parameter::Mandatory {
@@ -36,55 +37,57 @@ const ROOT_MENU: Menu<Output, Context> = Menu {
                     },
                 ],
             },
-            command: "foo",
-            help: Some(
-                "Makes a foo appear.
+        )
+        .help(
+            "Makes a foo appear.
 
 This is some extensive help text.
 
 It contains multiple paragraphs and should be preceeded by the parameter list.
 ",
-            ),
-        },
-        &Item {
-            item_type: ItemType::Callback {
+        ),
+        &Item::new(
+            "bar",
+            ItemType::Callback {
                 function: select_bar,
                 parameters: &[],
             },
-            command: "bar",
-            help: Some("fandoggles a bar"),
-        },
-        &Item {
-            item_type: ItemType::Menu(&Menu {
-                label: "sub",
-                items: &[
-                    &Item {
-                        item_type: ItemType::Callback {
-                            function: select_baz,
-                            parameters: &[],
-                        },
-                        command: "baz",
-                        help: Some("thingamobob a baz"),
-                    },
-                    &Item {
-                        item_type: ItemType::Callback {
-                            function: select_quux,
-                            parameters: &[],
-                        },
-                        command: "quux",
-                        help: Some("maximum quux"),
-                    },
-                ],
-                entry: Some(enter_sub),
-                exit: Some(exit_sub),
-            }),
-            command: "sub",
-            help: Some("enter sub-menu"),
-        },
+        )
+        .help("fandoggles a bar")
+        .aliases(&["fandoggle"]),
+        &Item::new(
+            "sub",
+            ItemType::Menu(
+                &Menu::new(
+                    "sub",
+                    &[
+                        &Item::new(
+                            "baz",
+                            ItemType::Callback {
+                                function: select_baz,
+                                parameters: &[],
+                            },
+                        )
+                        .help("thingamobob a baz"),
+                        &Item::new(
+                            "quux",
+                            ItemType::Callback {
+                                function: select_quux,
+                                parameters: &[],
+                            },
+                        )
+                        .help("maximum quux"),
+                    ],
+                )
+                .entry(enter_sub)
+                .exit(exit_sub),
+            ),
+        )
+        .help("enter sub-menu"),
     ],
-    entry: Some(enter_root),
-    exit: Some(exit_root),
-};
+)
+.entry(enter_root)
+.exit(exit_root);
 
 struct Output(pancurses::Window);
 
@@ -123,7 +126,7 @@ fn main() {
             }
             Some(Input::KeyDC) => break,
             Some(input) => {
-                r.interface.0.addstr(&format!("{:?}", input));
+                r.interface.0.addstr(format!("{:?}", input));
             }
             None => (),
         }
@@ -145,7 +148,7 @@ fn select_foo(
     args: &[&str],
     interface: &mut Output,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_foo. Args = {:?}", args).unwrap();
     writeln!(
         interface,
@@ -177,6 +180,7 @@ fn select_foo(
         ::menu::argument_finder(item, args, "no_such_arg")
     )
     .unwrap();
+    Status::Success
 }
 
 fn select_bar(
@@ -185,8 +189,9 @@ fn select_bar(
     args: &[&str],
     interface: &mut Output,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_bar. Args = {:?}", args).unwrap();
+    Status::Success
 }
 
 fn enter_sub(_menu: &Menu<Output, Context>, interface: &mut Output, _context: &mut Context) {
@@ -203,8 +208,9 @@ fn select_baz(
     args: &[&str],
     interface: &mut Output,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_baz: Args = {:?}", args).unwrap();
+    Status::Success
 }
 
 fn select_quux(
@@ -213,6 +219,7 @@ fn select_quux(
     args: &[&str],
     interface: &mut Output,
     _context: &mut Context,
-) {
+) -> Status {
     writeln!(interface, "In select_quux: Args = {:?}", args).unwrap();
+    Status::Success
 }
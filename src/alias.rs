@@ -0,0 +1,91 @@
+//! Aliases (or macros) defined by the operator at run-time, such as
+//! `alias rr "radio reset; radio status"`.
+#![deny(missing_docs)]
+
+use super::{matches_word, store::Store, Error, BUILTINS};
+
+/// A fixed-capacity store of aliases, held in a buffer you supply.
+///
+/// Each alias takes up three bytes, plus the length of its name and its
+/// expansion. Any space which is left over is used when an alias is run with
+/// extra arguments, which are added to the end of the expansion.
+pub struct Aliases<'a> {
+    store: Store<'a>,
+}
+
+impl<'a> Aliases<'a> {
+    /// Create an empty alias store, which keeps its aliases in `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            store: Store::new(buffer),
+        }
+    }
+
+    /// Define an alias, replacing any existing alias with the same name.
+    ///
+    /// Returns `Err(Error::InvalidName)` if `name` is empty, is the name of a
+    /// built-in command such as `alias` (in any case), or has whitespace, `;`
+    /// or `&` in it. Returns `Err(Error::TableFull)` if there is not enough
+    /// room left, or the name is over 255 bytes or the expansion over 65535
+    /// bytes, in which case any existing alias is left unchanged.
+    pub fn define(&mut self, name: &str, expansion: &str) -> Result<(), Error> {
+        if !is_valid_name(name) {
+            return Err(Error::InvalidName);
+        }
+        self.store.define(name, expansion)
+    }
+
+    /// Remove an alias. Returns `false` if there was no such alias.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.store.remove(name)
+    }
+
+    /// Get the expansion of an alias.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.store.entries().get(name)
+    }
+
+    /// Iterate through the aliases, as `(name, expansion)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.store.entries().iter()
+    }
+
+    /// Expand an alias, adding `args` to the end of its expansion. With
+    /// `ignore_case`, `name` matches an alias whatever its case.
+    ///
+    /// Returns `None` if there is no such alias, or `Some(Err(..))` if there is
+    /// not enough space left to add the arguments.
+    pub(crate) fn expand(
+        &mut self,
+        name: &str,
+        args: &str,
+        ignore_case: bool,
+    ) -> Option<Result<&str, Error>> {
+        let (store, scratch) = self.store.split_free();
+        let expansion = store.get_matching(|entry| matches_word(name, entry, ignore_case))?;
+        if args.is_empty() {
+            return Some(Ok(expansion));
+        }
+        let len = expansion.len() + 1 + args.len();
+        if len > scratch.len() {
+            return Some(Err(Error::TableFull));
+        }
+        scratch[0..expansion.len()].copy_from_slice(expansion.as_bytes());
+        scratch[expansion.len()] = b' ';
+        scratch[expansion.len() + 1..len].copy_from_slice(args.as_bytes());
+        // We just joined two strings and an ASCII space
+        Some(Ok(core::str::from_utf8(&scratch[0..len]).unwrap()))
+    }
+}
+
+/// Can this be the name of an alias? It mustn't hide a built-in command, or be
+/// impossible to type as a single command.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || c == ';' || c == '&')
+        && !BUILTINS
+            .iter()
+            .any(|(word, _, _)| word.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,71 @@
+//! Keeping a record of the commands the operator runs.
+//!
+//! Give the [`Runner`](super::Runner) a function with
+//! [`Runner::set_audit`](super::Runner::set_audit), and it is called just
+//! before each command's callback runs, and again just after with the
+//! [`Status`] it returned. A resumable command (see
+//! [`ItemType::Resumable`](super::ItemType::Resumable)) which asks a question
+//! or keeps running gets one more record when it finishes. A command which
+//! can't be run (e.g. because its arguments are wrong) gets a
+//! [`Stage::Rejected`] record instead. What you do with the [`Record`] is up
+//! to you - you might write it to flash, or send it somewhere else.
+//!
+//! Entering a sub-menu, giving a password for a
+//! [gate](super::Menu::gate), and the `enable`, `disable` and `logout`
+//! commands are recorded too. Logging out after an
+//! [idle timeout](super::Runner::set_idle_timeout) is recorded as `logout`.
+//!
+//! Arguments given for a parameter wrapped in
+//! [`Parameter::Secret`](super::Parameter::Secret) are replaced with
+//! [`REDACTED`]. For a
+//! `--name=value` parameter, the whole argument is replaced.
+#![deny(missing_docs)]
+
+use super::Status;
+
+/// Shown in place of an argument which is a secret
+pub const REDACTED: &str = "****";
+
+/// The type of function we call with each [`Record`].
+pub type AuditFn<T> = fn(context: &mut T, record: &Record);
+
+/// When a [`Record`] was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// The command is about to run
+    Before,
+    /// The command has run, and this is what it returned
+    After(Status),
+    /// A resumable command which returned [`Status::Ask`] or
+    /// [`Status::Pending`] has finished, and this is what it returned in the
+    /// end. The arguments are not given again - the [`Record::id`] is the
+    /// same as in the [`Stage::Before`] record.
+    Finished(Status),
+    /// The command was not run, for this reason (e.g. `"too many
+    /// arguments"` or `"wrong password"`)
+    Rejected(&'static str),
+    /// The operator entered the sub-menu. The path ends with it.
+    Entered,
+}
+
+/// A command the operator ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'r> {
+    /// Counts up with each command (wrapping around), so the records for
+    /// one command can be matched up
+    pub id: u32,
+    /// The sub-menus the operator was in, from the top: the label of each, and
+    /// the index it was generated with if it is a generated sub-menu. This is
+    /// empty at the root menu.
+    pub path: &'r [(&'r str, Option<usize>)],
+    /// The command, as named in its [`Item`](super::Item) - even if the
+    /// operator typed an alias for it
+    pub command: &'r str,
+    /// The arguments given, with any secrets redacted
+    pub args: &'r [&'r str],
+    /// The operator's privilege level. See
+    /// [`Runner::set_enable`](super::Runner::set_enable).
+    pub privilege: u8,
+    /// Whether the command is about to run, has run, or was not run
+    pub stage: Stage,
+}
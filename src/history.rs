@@ -0,0 +1,93 @@
+//! Keeping secrets out of the `noline` line editor's history.
+//!
+//! Only available with the `noline` feature.
+#![deny(missing_docs)]
+
+use noline::history::{CircularSlice, History};
+
+use super::{is_yes_flag, matches_word, next_command, ItemType, Menu, SecretArgs};
+
+/// How many levels of sub-menu we look through
+const MAX_DEPTH: usize = 5;
+
+/// Wraps the history of a `noline` editor, and leaves out any line which
+/// gives a value for a secret parameter (see [`Parameter`](super::Parameter)),
+/// so it can't be brought back with the up arrow.
+///
+/// Works with any [`History`], such as `SliceHistory` or `UnboundedHistory`.
+/// It looks for the command in `menu` and all of its sub-menus, so a line is
+/// left out if a command with that name takes a secret in any of them.
+///
+/// ```rust,ignore
+/// let history = SkipSecrets::new(SliceHistory::new(&mut history_buffer), &ROOT_MENU);
+/// let mut editor = Editor::new(LineBuffer::from_slice(&mut line_buffer), history, &mut io)?;
+/// let mut r = Runner::new(ROOT_MENU, &mut editor, io, &mut context);
+/// ```
+pub struct SkipSecrets<'m, H, I, T> {
+    history: H,
+    menu: &'m Menu<'m, I, T>,
+}
+
+impl<'m, H, I, T> SkipSecrets<'m, H, I, T> {
+    /// Wrap `history`, leaving out lines which give secrets to commands in
+    /// `menu`.
+    pub fn new(history: H, menu: &'m Menu<'m, I, T>) -> Self {
+        Self { history, menu }
+    }
+}
+
+impl<H, I, T> History for SkipSecrets<'_, H, I, T>
+where
+    H: History,
+{
+    fn get_entry(&self, index: usize) -> Option<CircularSlice<'_>> {
+        self.history.get_entry(index)
+    }
+
+    fn add_entry<'a>(&mut self, entry: &'a str) -> Result<(), &'a str> {
+        let mut remaining = entry;
+        loop {
+            let (command, next) = next_command(remaining);
+            if gives_secret(self.menu, command, 0) {
+                // Pretend we kept it
+                return Ok(());
+            }
+            let Some((_, rest)) = next else {
+                break;
+            };
+            remaining = rest;
+        }
+        self.history.add_entry(entry)
+    }
+
+    fn number_of_entries(&self) -> usize {
+        self.history.number_of_entries()
+    }
+}
+
+/// Does this command give a secret to a command of that name in `menu`, or
+/// one of its sub-menus?
+fn gives_secret<I, T>(menu: &Menu<I, T>, command: &str, depth: usize) -> bool {
+    let mut words = command.split_whitespace();
+    let Some(cmd) = words.next() else {
+        return false;
+    };
+    menu.all_items().any(|item| match item.item_type {
+        ItemType::Callback { parameters, .. } | ItemType::Resumable { parameters, .. } => {
+            // Ignore case, in case the runner does
+            if !item.names().any(|name| matches_word(cmd, name, true)) {
+                return false;
+            }
+            let mut secrets = SecretArgs::new(parameters, true);
+            words
+                .clone()
+                .filter(|arg| !(item.confirm && is_yes_flag(arg)))
+                .any(|arg| secrets.is_secret(arg))
+        }
+        ItemType::Menu(sub_menu) => depth < MAX_DEPTH && gives_secret(sub_menu, command, depth + 1),
+        ItemType::Generated { generator, .. } => {
+            depth < MAX_DEPTH && gives_secret(&generator(0), command, depth + 1)
+        }
+        ItemType::_Dummy => false,
+    })
+}
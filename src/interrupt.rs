@@ -0,0 +1,80 @@
+//! Support for interrupting long-running commands with Ctrl-C.
+#![deny(missing_docs)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The byte sent by pressing Ctrl-C
+pub const CTRL_C: u8 = 0x03;
+
+/// A request to stop the command which is running.
+///
+/// Put one somewhere your callbacks can see it (such as a `static`), and give
+/// the [`Runner`](super::Runner) a reference with
+/// [`Runner::set_interrupt`](super::Runner::set_interrupt). A long-running
+/// callback should call [`Interrupt::poll`] (or
+/// [`Interrupt::is_requested`]) now and again, and return early if it says
+/// yes. The runner then prints `^C`, skips the rest of the line, and goes
+/// back to the prompt.
+///
+/// ```rust
+/// # use menu::interrupt::Interrupt;
+/// static INTERRUPT: Interrupt = Interrupt::new();
+///
+/// // In an interrupt handler, or another task
+/// INTERRUPT.request();
+/// assert!(INTERRUPT.is_requested());
+/// ```
+#[derive(Debug, Default)]
+pub struct Interrupt {
+    requested: AtomicBool,
+}
+
+impl Interrupt {
+    /// Create a new `Interrupt`, which has not been requested.
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+        }
+    }
+
+    /// Ask the running command to stop. This can be called from anywhere,
+    /// such as an interrupt handler.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Has someone asked the running command to stop?
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    /// Look at any input waiting on `interface` for a Ctrl-C, then say
+    /// whether the running command has been asked to stop.
+    ///
+    /// Any other input which is waiting is thrown away.
+    pub fn poll<I>(&self, interface: &mut I) -> bool
+    where
+        I: embedded_io::Read + embedded_io::ReadReady,
+    {
+        while let Ok(true) = interface.read_ready() {
+            let mut byte = [0u8];
+            match interface.read(&mut byte) {
+                Ok(1) if byte[0] == CTRL_C => self.request(),
+                Ok(1) => {}
+                _ => break,
+            }
+        }
+        self.is_requested()
+    }
+
+    /// Say whether the running command was asked to stop, and forget the
+    /// request.
+    pub(crate) fn take(&self) -> bool {
+        // Only load and store, as some targets can't swap atomically
+        let requested = self.is_requested();
+        if requested {
+            self.requested.store(false, Ordering::Relaxed);
+        }
+        requested
+    }
+}
@@ -4,19 +4,150 @@
 //! zero heap allocation.
 #![no_std]
 
+use core::cell::Cell;
+
 use menu_manager::MenuManager;
 
 #[cfg(feature = "noline")]
 use noline::{error::NolineError, history::History, line_buffer::Buffer, sync_editor::Editor};
 
+pub mod alias;
+pub mod audit;
+#[cfg(feature = "noline")]
+pub mod history;
+pub mod interrupt;
 pub mod menu_manager;
+pub mod newline;
+pub mod prompt;
+pub mod script;
+pub mod session;
+mod store;
+#[cfg(feature = "style")]
+pub mod style;
+#[cfg(feature = "variables")]
+pub mod variables;
+pub mod watch;
 
 /// The type of function we call when we enter/exit a menu.
 pub type MenuCallbackFn<I, T> = fn(menu: &Menu<I, T>, interface: &mut I, context: &mut T);
 
+/// The type of function we call to decide whether an item with
+/// [`Visibility::When`] should be shown.
+pub type VisibilityFn<T> = fn(context: &T) -> bool;
+
+/// The type of function we call to decide whether an item can be used right
+/// now. Return `Err` with a short reason if it cannot.
+pub type EnabledFn<T> = fn(context: &T) -> Result<(), &'static str>;
+
+/// The type of function we call to produce a generated sub-menu. It is given
+/// the index the user typed after the command (e.g. `3` for `ch3`).
+pub type MenuGeneratorFn<'a, I, T> = fn(index: usize) -> Menu<'a, I, T>;
+
 /// The type of function we call when we a valid command has been entered.
-pub type ItemCallbackFn<I, T> =
-    fn(menu: &Menu<I, T>, item: &Item<I, T>, args: &[&str], interface: &mut I, context: &mut T);
+///
+/// Return whether the command worked, so that `cmd1 && cmd2` knows whether
+/// to carry on.
+pub type ItemCallbackFn<I, T> = fn(
+    menu: &Menu<I, T>,
+    item: &Item<I, T>,
+    args: &[&str],
+    interface: &mut I,
+    context: &mut T,
+) -> Status;
+
+/// The type of function we call when an [`ItemType::Resumable`] command
+/// carries on, such as when the operator has answered its question.
+pub type ItemResumeFn<I, T> = fn(
+    menu: &Menu<I, T>,
+    item: &Item<I, T>,
+    event: Event,
+    interface: &mut I,
+    context: &mut T,
+) -> Status;
+
+/// Whether a command worked or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The command did what was asked
+    Success,
+    /// The command failed, and has already said why
+    Failure,
+    /// The command needs an answer before it can carry on. When the operator
+    /// has answered, the item's [`ItemResumeFn`] is called.
+    ///
+    /// Only [`ItemType::Resumable`] commands can ask questions, and the rest
+    /// of the line is skipped.
+    Ask(Question),
+    /// The command is still running. Until it returns something else, the
+    /// item's [`ItemResumeFn`] is called with [`Event::Poll`] each time you
+    /// call [`Runner::poll`], and the prompt is not shown.
+    ///
+    /// Only [`ItemType::Resumable`] commands can keep running, and the rest
+    /// of the line is skipped.
+    Pending,
+}
+
+/// A question for the operator, asked by returning [`Status::Ask`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Question {
+    /// Shown instead of the usual prompt, e.g. `"Are you sure? [y/N] "`
+    pub prompt: &'static str,
+    /// What sort of answer we want
+    pub kind: AnswerKind,
+}
+
+impl Question {
+    /// Ask for a line of text.
+    pub const fn line(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            kind: AnswerKind::Line,
+        }
+    }
+
+    /// Ask a yes or no question. Anything other than `y` or `yes` means no.
+    pub const fn yes_no(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            kind: AnswerKind::YesNo,
+        }
+    }
+
+    /// Ask for a password, which is shown as `*` as it is typed.
+    pub const fn password(prompt: &'static str) -> Self {
+        Self {
+            prompt,
+            kind: AnswerKind::Password,
+        }
+    }
+}
+
+/// The sort of answer a [`Question`] wants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerKind {
+    /// A line of text, given as [`Event::Answer`]
+    Line,
+    /// Yes or no, given as [`Event::Confirmed`]
+    YesNo,
+    /// A line of text which is not echoed, given as [`Event::Answer`]
+    Password,
+}
+
+/// Why an [`ItemType::Resumable`] command is carrying on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'s> {
+    /// The answer to an [`AnswerKind::Line`] or [`AnswerKind::Password`]
+    /// question
+    Answer(&'s str),
+    /// The answer to an [`AnswerKind::YesNo`] question
+    Confirmed(bool),
+    /// The command returned [`Status::Pending`], and should do some more work
+    Poll,
+    /// The operator gave up on the command (e.g. with Ctrl-C) while it was
+    /// asking a question or still running, or the session timed out. Tidy up
+    /// and return [`Status::Failure`] - the command won't be carried on again.
+    Cancelled,
+}
 
 #[derive(Debug)]
 /// Describes a parameter to the command
@@ -51,6 +182,22 @@ pub enum Parameter<'a> {
         /// Help text
         help: Option<&'a str>,
     },
+    /// A [`Parameter::Mandatory`], [`Parameter::Optional`] or
+    /// [`Parameter::NamedValue`] parameter whose value is a secret, such as a
+    /// password. It is shown as `*`s as it is typed, and kept out of the
+    /// [`audit`] record and the `noline` history.
+    Secret(&'a Parameter<'a>),
+}
+
+impl<'a> Parameter<'a> {
+    /// This parameter, without any [`Parameter::Secret`] wrapper
+    fn plain(&self) -> &Parameter<'a> {
+        let mut param = self;
+        while let Parameter::Secret(inner) = param {
+            param = inner;
+        }
+        param
+    }
 }
 
 /// Do we enter a sub-menu when this command is entered, or call a specific
@@ -66,12 +213,43 @@ where
         /// The list of parameters for this function. Pass an empty list if there aren't any.
         parameters: &'a [Parameter<'a>],
     },
+    /// Call a function when this command is entered, which can then ask
+    /// questions (see [`Status::Ask`]) and carry on later
+    Resumable {
+        /// The function to call when the command is entered
+        function: ItemCallbackFn<I, T>,
+        /// The list of parameters for this function. Pass an empty list if there aren't any.
+        parameters: &'a [Parameter<'a>],
+        /// The function to call when the command carries on
+        resume: ItemResumeFn<I, T>,
+    },
     /// This item is a sub-menu you can enter
     Menu(&'a Menu<'a, I, T>),
+    /// This item is a family of sub-menus, entered by typing the command
+    /// followed by an index with no leading zeros (e.g. `ch0`, `ch1`, ...).
+    /// `help` with just the command describes the whole family.
+    Generated {
+        /// The function which produces the sub-menu for an index
+        generator: MenuGeneratorFn<'a, I, T>,
+        /// How many sub-menus there are. The index must be less than this.
+        count: usize,
+    },
     /// Internal use only - do not use
     _Dummy,
 }
 
+/// Does this item appear in `help` output and in Tab completion?
+pub enum Visibility<T> {
+    /// Always shown
+    Visible,
+    /// Never shown. Whether it can still be run is up to
+    /// [`Runner::set_dispatch_hidden`].
+    Hidden,
+    /// Shown only when this function returns `true`. When it is not shown,
+    /// it is treated like [`Visibility::Hidden`].
+    When(VisibilityFn<T>),
+}
+
 /// An `Item` is a what our menus are made from. Each item has a `name` which
 /// you have to enter to select this item. Each item can also have zero or
 /// more parameters, and some optional help text.
@@ -82,12 +260,128 @@ where
     /// The word you need to enter to activate this item. It is recommended
     /// that you avoid whitespace in this string.
     pub command: &'a str,
+    /// Alternative words which also activate this item (e.g. old names of a
+    /// renamed command).
+    pub aliases: &'a [&'a str],
+    /// Whether this item is shown in `help` and Tab completion.
+    pub visibility: Visibility<T>,
+    /// Decides whether this item can be used in the current state. If this
+    /// is `None`, the item can always be used.
+    pub enabled: Option<EnabledFn<T>>,
+    /// The lowest privilege level which can see and use this item. Use `0`
+    /// to let anyone use it. See [`Runner::set_enable`].
+    pub privilege: u8,
+    /// Ask `Really run 'erase'? [y/N]` before calling the function, unless
+    /// the operator gave `--yes` (or `-y`). Scripts must give `--yes`, as
+    /// they can't answer questions. This has no effect on sub-menus.
+    pub confirm: bool,
     /// Optional help text. Printed if you enter `help`.
     pub help: Option<&'a str>,
     /// The type of this item - menu, callback, etc.
     pub item_type: ItemType<'a, I, T>,
 }
 
+impl<'a, I, T> Item<'a, I, T> {
+    /// Create an item which does `item_type` when `command` is entered.
+    ///
+    /// It has no help text or aliases to begin with - add them with the
+    /// methods below, which can be chained:
+    ///
+    /// ```rust
+    /// # use menu::*;
+    /// # fn reboot(_: &Menu<(), ()>, _: &Item<(), ()>, _: &[&str], _: &mut (), _: &mut ()) -> Status {
+    /// #     Status::Success
+    /// # }
+    /// const REBOOT: Item<(), ()> = Item::new(
+    ///     "reboot",
+    ///     ItemType::Callback {
+    ///         function: reboot,
+    ///         parameters: &[],
+    ///     },
+    /// )
+    /// .help("Reboot the unit")
+    /// .aliases(&["reset"]);
+    /// ```
+    pub const fn new(command: &'a str, item_type: ItemType<'a, I, T>) -> Self {
+        Self {
+            command,
+            aliases: &[],
+            visibility: Visibility::Visible,
+            enabled: None,
+            privilege: 0,
+            confirm: false,
+            help: None,
+            item_type,
+        }
+    }
+
+    /// Give this item some help text.
+    pub const fn help(mut self, help: &'a str) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    /// Give this item some alternative words which also activate it.
+    pub const fn aliases(mut self, aliases: &'a [&'a str]) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Set whether this item is shown in `help` and Tab completion.
+    pub const fn visibility(mut self, visibility: Visibility<T>) -> Self {
+        self.visibility = visibility;
+        self
+    }
+
+    /// Give this item a function which decides whether it can be used in the
+    /// current state.
+    pub const fn enabled(mut self, enabled: EnabledFn<T>) -> Self {
+        self.enabled = Some(enabled);
+        self
+    }
+
+    /// Keep this item from operators below privilege level `privilege`.
+    pub const fn privilege(mut self, privilege: u8) -> Self {
+        self.privilege = privilege;
+        self
+    }
+
+    /// Ask the operator to confirm before running this item, unless they
+    /// give `--yes`.
+    pub const fn confirm(mut self) -> Self {
+        self.confirm = true;
+        self
+    }
+
+    /// All the words which activate this item - the command, then any aliases.
+    fn names(&self) -> impl Iterator<Item = &'a str> + Clone {
+        core::iter::once(self.command).chain(self.aliases.iter().copied())
+    }
+
+    /// Can this item be used, given the current context? If not, why not?
+    fn check_enabled(&self, context: &T) -> Result<(), &'static str> {
+        match self.enabled {
+            Some(f) => f(context),
+            None => Ok(()),
+        }
+    }
+
+    /// Should this item be shown, given the current context?
+    fn is_visible(&self, context: &T) -> bool {
+        match self.visibility {
+            Visibility::Visible => true,
+            Visibility::Hidden => false,
+            Visibility::When(f) => f(context),
+        }
+    }
+
+    /// Can an operator at this privilege level see and use this item?
+    fn is_allowed(&self, level: u8) -> bool {
+        self.privilege <= level
+            && !matches!(self.item_type, ItemType::Menu(menu) if menu.privilege > level)
+    }
+}
+
 /// A `Menu` is made of one or more `Item`s.
 pub struct Menu<'a, I, T>
 where
@@ -96,6 +390,19 @@ where
     /// Each menu has a label which is visible in the prompt, unless you are
     /// the root menu.
     pub label: &'a str,
+    /// For a menu produced by an [`ItemType::Generated`] item (or a sub-menu
+    /// of one), the index it was generated with. This is filled in by the
+    /// [`MenuManager`] - see [`Menu::index`].
+    index: Option<usize>,
+    /// Extra items which are added and removed at run-time (e.g. an
+    /// [`ItemTable`]). These come after the items in `items`.
+    pub dynamic_items: Option<&'a dyn ItemSource<I, T>>,
+    /// If set, the operator must give a password before they can enter this
+    /// menu.
+    pub gate: Option<&'a session::Gate<T>>,
+    /// The lowest privilege level which can see and enter this menu. Use
+    /// `0` to let anyone in. See [`Runner::set_enable`].
+    pub privilege: u8,
     /// A slice of menu items in this menu.
     pub items: &'a [&'a Item<'a, I, T>],
     /// A function to call when this menu is entered. If this is the root menu, this is called when the runner is created.
@@ -104,6 +411,181 @@ where
     pub exit: Option<MenuCallbackFn<I, T>>,
 }
 
+impl<'a, I, T> Menu<'a, I, T> {
+    /// Create a menu with this label and these items.
+    ///
+    /// It has no entry or exit functions to begin with - add them with the
+    /// methods below, which can be chained:
+    ///
+    /// ```rust
+    /// # use menu::*;
+    /// # fn enter_sub(_: &Menu<(), ()>, _: &mut (), _: &mut ()) {}
+    /// const SUB_MENU: Menu<(), ()> = Menu::new("sub", &[]).entry(enter_sub);
+    /// ```
+    pub const fn new(label: &'a str, items: &'a [&'a Item<'a, I, T>]) -> Self {
+        Self {
+            label,
+            index: None,
+            dynamic_items: None,
+            gate: None,
+            privilege: 0,
+            items,
+            entry: None,
+            exit: None,
+        }
+    }
+
+    /// Call this function when the menu is entered.
+    pub const fn entry(mut self, entry: MenuCallbackFn<I, T>) -> Self {
+        self.entry = Some(entry);
+        self
+    }
+
+    /// Call this function when the menu is exited.
+    pub const fn exit(mut self, exit: MenuCallbackFn<I, T>) -> Self {
+        self.exit = Some(exit);
+        self
+    }
+
+    /// Ask for a password before the operator can enter this menu.
+    pub const fn gate(mut self, gate: &'a session::Gate<T>) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// Keep this menu from operators below privilege level `privilege`.
+    pub const fn privilege(mut self, privilege: u8) -> Self {
+        self.privilege = privilege;
+        self
+    }
+
+    /// For a menu produced by an [`ItemType::Generated`] item (or a sub-menu
+    /// of one), the index it was generated with, so callbacks can tell which
+    /// one they are in.
+    pub const fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Add the items in `source` (e.g. an [`ItemTable`]) after the items
+    /// given to [`Menu::new`].
+    pub const fn dynamic_items(mut self, source: &'a dyn ItemSource<I, T>) -> Self {
+        self.dynamic_items = Some(source);
+        self
+    }
+
+    /// Get the item at the given position. The items in `items` come first,
+    /// followed by every slot in `dynamic_items`.
+    fn item(&self, index: usize) -> Option<&'a Item<'a, I, T>> {
+        match self.items.get(index) {
+            Some(item) => Some(*item),
+            None => self.dynamic_items?.slot(index - self.items.len()),
+        }
+    }
+
+    /// Every item in this menu, along with its position.
+    fn indexed_items(&self) -> impl Iterator<Item = (usize, &'a Item<'a, I, T>)> + Clone + 'a {
+        let offset = self.items.len();
+        let items: &'a [&'a Item<'a, I, T>] = self.items;
+        items
+            .iter()
+            .copied()
+            .enumerate()
+            .chain(self.dynamic_items.into_iter().flat_map(move |source| {
+                (0..source.capacity())
+                    .filter_map(move |i| source.slot(i).map(|item| (offset + i, item)))
+            }))
+    }
+
+    /// Every item in this menu.
+    fn all_items(&self) -> impl Iterator<Item = &'a Item<'a, I, T>> + Clone + 'a {
+        self.indexed_items().map(|(_, item)| item)
+    }
+}
+
+/// Supplies menu items which are only known at run-time, for use in
+/// [`Menu::dynamic_items`].
+///
+/// The item in each slot may change at any time, but a slot should not be
+/// emptied while the user is inside a sub-menu held in that slot.
+pub trait ItemSource<I, T> {
+    /// How many slots are there?
+    fn capacity(&self) -> usize;
+
+    /// Get the item in this slot, if there is one.
+    fn slot(&self, index: usize) -> Option<&Item<'_, I, T>>;
+}
+
+/// A fixed-capacity table of menu items, which can be registered and removed
+/// at run-time (e.g. when optional hardware is detected at boot).
+///
+/// Items are registered through a shared reference, so the table can be
+/// placed in a [`Menu`] and still be updated afterwards. This uses a
+/// [`Cell`] for each slot, so the table is not `Sync` and can't be a
+/// `static` - make it a local variable (e.g. in `main`) which lives as long
+/// as the [`Runner`].
+pub struct ItemTable<'a, I, T, const N: usize> {
+    slots: [Cell<Option<&'a Item<'a, I, T>>>; N],
+}
+
+impl<'a, I, T, const N: usize> ItemTable<'a, I, T, N> {
+    /// Create a new, empty, table.
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { Cell::new(None) }; N],
+        }
+    }
+
+    /// Add an item to the table.
+    ///
+    /// Returns `Err(Error::Duplicate)` if the table already has an item with
+    /// the same command, or `Err(Error::TableFull)` if there are no free
+    /// slots.
+    pub fn register(&self, item: &'a Item<'a, I, T>) -> Result<(), Error> {
+        if self
+            .slots
+            .iter()
+            .any(|slot| slot.get().is_some_and(|old| old.command == item.command))
+        {
+            return Err(Error::Duplicate);
+        }
+        let slot = self
+            .slots
+            .iter()
+            .find(|slot| slot.get().is_none())
+            .ok_or(Error::TableFull)?;
+        slot.set(Some(item));
+        Ok(())
+    }
+
+    /// Remove the item with the given command from the table, returning it.
+    ///
+    /// If it is a sub-menu the operator is in, the [`Runner`] takes them back
+    /// out of it (without calling its exit function) before it next does
+    /// anything.
+    pub fn remove(&self, command: &str) -> Option<&'a Item<'a, I, T>> {
+        self.slots
+            .iter()
+            .find(|slot| slot.get().is_some_and(|item| item.command == command))
+            .and_then(|slot| slot.take())
+    }
+}
+
+impl<I, T, const N: usize> Default for ItemTable<'_, I, T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, T, const N: usize> ItemSource<I, T> for ItemTable<'_, I, T, N> {
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn slot(&self, index: usize) -> Option<&Item<'_, I, T>> {
+        self.slots.get(index)?.get()
+    }
+}
+
 /// This structure handles the menu. You feed it bytes as they are read from
 /// the console and it executes menu actions when commands are typed in
 /// (followed by Enter).
@@ -116,8 +598,77 @@ pub struct Runner<'a, I, T, B: ?Sized> {
 
 struct InnerRunner<'a, I, T> {
     menu_mgr: menu_manager::MenuManager<'a, I, T>,
+    ignore_case: bool,
+    dispatch_hidden: bool,
+    repeat: bool,
+    aliases: Option<alias::Aliases<'a>>,
+    #[cfg(feature = "variables")]
+    variables: Option<variables::Variables<'a>>,
+    /// Whether we were given variables. They are taken out of `variables`
+    /// while a command runs, but `help` still lists `set` and `unset`.
+    #[cfg(feature = "variables")]
+    have_variables: bool,
+    last_status: Status,
+    clock: Option<&'a mut dyn watch::TimeSource>,
+    watch: Option<watch::Watch<I>>,
+    interrupt: Option<&'a interrupt::Interrupt>,
+    task: Option<Task>,
+    session: session::Session,
+    enable: Option<session::EnableFn<T>>,
+    audit: Option<audit::AuditFn<T>>,
+    /// Counts up with each command, for [`audit::Record::id`]
+    audit_id: u32,
+    #[cfg(feature = "style")]
+    theme: Option<style::Theme>,
+    prompt_fn: Option<prompt::PromptFn<T>>,
+    /// Wrap `help` text to this many columns
+    help_width: Option<usize>,
+    /// The command line waiting to be confirmed, or being watched
+    saved_line: [u8; MAX_SAVED_LEN],
+}
+
+/// A resumable command which has not finished yet
+struct Task {
+    /// Where the item is in the current menu
+    index: usize,
+    state: TaskState,
 }
 
+/// What a resumable command is waiting for
+enum TaskState {
+    /// An answer to this question
+    Asking(Question),
+    /// To be polled again
+    Running,
+    /// A password, so we can enter the menu at `index` (which is generated
+    /// with this index, if it is generated)
+    Login(Question, Option<usize>),
+    /// A password, so we can raise the privilege level to this
+    Enable(Question, u8),
+    /// A yes or no, so we can run the command line in
+    /// `InnerRunner::saved_line`, which is this long
+    Confirm(usize),
+    /// Time to pass, so `watch` can run the command line in
+    /// `InnerRunner::saved_line` again
+    Watch {
+        /// How long the command line is
+        len: usize,
+        /// How long to wait between runs
+        period_ms: u32,
+        /// When it last ran
+        last_ms: u32,
+        /// What it returned when it last ran
+        status: Status,
+    },
+}
+
+/// The longest command line which can wait to be confirmed or be watched
+const MAX_SAVED_LEN: usize = 128;
+
+/// Used to ask whether to run an item which needs confirming. The prompt
+/// comes from [`InnerRunner::question_prompt`].
+const CONFIRM: Question = Question::yes_no("");
+
 /// Describes the ways in which the API can fail
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
@@ -125,6 +676,14 @@ pub enum Error {
     NotACallbackItem,
     /// The argument you asked for was not found
     NotFound,
+    /// There was no room left in an [`ItemTable`], or some other fixed-size
+    /// store such as [`alias::Aliases`]
+    TableFull,
+    /// The name can't be used for an alias, as it is a built-in command or
+    /// has a space, `;` or `&` in it
+    InvalidName,
+    /// There is already an item with this command in the [`ItemTable`]
+    Duplicate,
 }
 
 impl core::fmt::Display for Error {
@@ -151,14 +710,16 @@ pub fn argument_finder<'a, I, T>(
     argument_list: &'a [&'a str],
     name_to_find: &'a str,
 ) -> Result<Option<&'a str>, Error> {
-    let ItemType::Callback { parameters, .. } = item.item_type else {
+    let (ItemType::Callback { parameters, .. } | ItemType::Resumable { parameters, .. }) =
+        item.item_type
+    else {
         return Err(Error::NotACallbackItem);
     };
     // Step 1 - Find `name_to_find` in the parameter list.
     let mut found_param = None;
     let mut mandatory_count = 0;
     let mut optional_count = 0;
-    for param in parameters.iter() {
+    for param in parameters.iter().map(Parameter::plain) {
         match param {
             Parameter::Mandatory { parameter_name, .. } => {
                 mandatory_count += 1;
@@ -182,6 +743,7 @@ pub fn argument_finder<'a, I, T>(
                     found_param = Some((param, 0));
                 }
             }
+            Parameter::Secret(_) => unreachable!(),
         }
     }
     // Step 2 - What sort of parameter is it?
@@ -246,6 +808,266 @@ pub fn argument_finder<'a, I, T>(
     }
 }
 
+/// How many bytes of `--named` arguments can be re-written in the case they
+/// were declared in, when ignoring case.
+const MAX_RENAMED_LEN: usize = 128;
+
+/// Is this answer to a yes or no question a yes?
+fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim();
+    answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes")
+}
+
+/// Does this argument say to run a command without asking for confirmation?
+fn is_yes_flag(arg: &str) -> bool {
+    arg == "--yes" || arg == "-y"
+}
+
+/// Write `text` re-flowed to fit in `width` columns, with every line
+/// indented by `indent` spaces. Lines in the same paragraph are joined, and
+/// blank lines between paragraphs are kept.
+fn write_wrapped<I>(interface: &mut I, text: &str, indent: usize, width: usize)
+where
+    I: embedded_io::Write,
+{
+    // How far along the current line we are, or zero if nothing is on it yet
+    let mut column = 0;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            // A paragraph break
+            if column > 0 {
+                writeln!(interface).unwrap();
+                column = 0;
+            }
+            writeln!(interface).unwrap();
+            continue;
+        }
+        for word in line.split_whitespace() {
+            let len = word.chars().count();
+            if column > 0 && column + 1 + len > width {
+                writeln!(interface).unwrap();
+                column = 0;
+            }
+            if column == 0 {
+                write!(interface, "{:indent$}{}", "", word).unwrap();
+                column = indent + len;
+            } else {
+                write!(interface, " {}", word).unwrap();
+                column += 1 + len;
+            }
+        }
+    }
+    if column > 0 {
+        writeln!(interface).unwrap();
+    }
+}
+
+/// Compare a word typed by the user against a command or parameter name.
+fn matches_word(typed: &str, name: &str, ignore_case: bool) -> bool {
+    if ignore_case {
+        typed.eq_ignore_ascii_case(name)
+    } else {
+        typed == name
+    }
+}
+
+/// Words longer than this never get suggestions.
+const MAX_SUGGESTION_LEN: usize = 32;
+
+/// How many single-character edits a suggestion can be away from what was
+/// typed.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Work out the Levenshtein distance between two words, without allocating.
+///
+/// Returns `None` if `name` is longer than [`MAX_SUGGESTION_LEN`].
+fn edit_distance(typed: &str, name: &str, ignore_case: bool) -> Option<usize> {
+    let name = name.as_bytes();
+    if name.len() > MAX_SUGGESTION_LEN {
+        return None;
+    }
+    let same = |a: u8, b: u8| {
+        if ignore_case {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+    // `row[j]` is the distance between the first `i` bytes of `typed` and
+    // the first `j` bytes of `name`.
+    let mut row = [0usize; MAX_SUGGESTION_LEN + 1];
+    for (j, slot) in row.iter_mut().enumerate() {
+        *slot = j;
+    }
+    for (i, &a) in typed.as_bytes().iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in name.iter().enumerate() {
+            let substitution = diagonal + usize::from(!same(a, b));
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    Some(row[name.len()])
+}
+
+/// If any of `candidates` is close to what was typed, print them out as
+/// suggestions. Only the closest matches are printed.
+fn suggest<'b, I>(
+    interface: &mut I,
+    typed: &str,
+    prefix: &str,
+    candidates: impl Iterator<Item = &'b str> + Clone,
+    ignore_case: bool,
+) where
+    I: embedded_io::Write,
+{
+    let distance = |name| {
+        edit_distance(typed, name, ignore_case)
+            .filter(|d| *d <= MAX_SUGGESTION_DISTANCE && *d < typed.len())
+    };
+    let Some(best) = candidates.clone().filter_map(distance).min() else {
+        return;
+    };
+    write!(interface, "Did you mean").unwrap();
+    let mut first = true;
+    for name in candidates.filter(|name| distance(name) == Some(best)) {
+        let separator = if first { " " } else { " or " };
+        write!(interface, "{}\"{}{}\"", separator, prefix, name).unwrap();
+        first = false;
+    }
+    writeln!(interface, "?").unwrap();
+}
+
+/// How one command on a line is joined to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    /// `;` - always run the next command
+    Then,
+    /// `&&` - only run the next command if this one worked
+    And,
+}
+
+/// The built-in commands: the word which runs it, how to use it, and what it
+/// does.
+const BUILTINS: [(&str, &str, &str); 11] = [
+    ("exit", "exit", "Leave this menu."),
+    (
+        "help",
+        "help [ <command> ]",
+        "Show this help, or get help on a specific command.",
+    ),
+    (
+        "alias",
+        "alias [ <name> [ <expansion> ] ]",
+        "List, show or define aliases.",
+    ),
+    ("unalias", "unalias <name>", "Remove an alias."),
+    (
+        "set",
+        "set [ <name> [ <value> ] ]",
+        "List, show or set variables.",
+    ),
+    ("unset", "unset <name>", "Remove a variable."),
+    (
+        "repeat",
+        "repeat <count> <command>",
+        "Run a command several times, stopping if it fails.",
+    ),
+    (
+        "watch",
+        "watch <ms> <command>",
+        "Run a command every <ms> milliseconds, until a key is pressed.",
+    ),
+    (
+        "logout",
+        "logout",
+        "Leave any password-protected menus, and log out.",
+    ),
+    (
+        "enable",
+        "enable <level>",
+        "Change to a privilege level, which may need a password.",
+    ),
+    (
+        "disable",
+        "disable",
+        "Go back to the lowest privilege level.",
+    ),
+];
+
+/// Split the first command off a line. Returns the command and, if there is
+/// one, the separator after it and the rest of the line. Separators inside
+/// double quotes are ignored.
+fn next_command(line: &str) -> (&str, Option<(Separator, &str)>) {
+    let bytes = line.as_bytes();
+    let mut quoted = false;
+    for (i, b) in bytes.iter().enumerate() {
+        match b {
+            b'"' => quoted = !quoted,
+            _ if quoted => {}
+            b';' => return (&line[..i], Some((Separator::Then, &line[i + 1..]))),
+            b'&' if bytes.get(i + 1) == Some(&b'&') => {
+                return (&line[..i], Some((Separator::And, &line[i + 2..])));
+            }
+            _ => {}
+        }
+    }
+    (line, None)
+}
+
+/// Works out which of a command's arguments are secrets. See [`Parameter`].
+struct SecretArgs<'p> {
+    parameters: &'p [Parameter<'p>],
+    /// How many positional arguments we've seen
+    positional: usize,
+    ignore_case: bool,
+}
+
+impl<'p> SecretArgs<'p> {
+    fn new(parameters: &'p [Parameter<'p>], ignore_case: bool) -> Self {
+        Self {
+            parameters,
+            positional: 0,
+            ignore_case,
+        }
+    }
+
+    /// Is this argument, which comes after the ones we've already been
+    /// given, a secret?
+    fn is_secret(&mut self, arg: &str) -> bool {
+        if let Some(tail) = arg.strip_prefix("--") {
+            let Some((name, _)) = tail.split_once('=') else {
+                return false;
+            };
+            return self.parameters.iter().any(|param| {
+                matches!(param, Parameter::Secret(_))
+                    && matches!(param.plain(), Parameter::NamedValue { parameter_name, .. }
+                        if matches_word(name, parameter_name, self.ignore_case))
+            });
+        }
+        let param = self
+            .parameters
+            .iter()
+            .filter(|p| {
+                matches!(
+                    p.plain(),
+                    Parameter::Mandatory { .. } | Parameter::Optional { .. }
+                )
+            })
+            .nth(self.positional);
+        self.positional += 1;
+        matches!(param, Some(Parameter::Secret(_)))
+    }
+}
+
+/// The kinds of output which a [`style::Theme`] can style
+enum Style {
+    Prompt,
+    Heading,
+    Error,
+}
+
 enum Outcome {
     CommandProcessed,
     NeedMore,
@@ -255,6 +1077,10 @@ impl<'a, I, T> core::clone::Clone for Menu<'a, I, T> {
     fn clone(&self) -> Menu<'a, I, T> {
         Menu {
             label: self.label,
+            index: self.index,
+            dynamic_items: self.dynamic_items,
+            gate: self.gate,
+            privilege: self.privilege,
             items: self.items,
             entry: self.entry,
             exit: self.exit,
@@ -265,7 +1091,12 @@ impl<'a, I, T> core::clone::Clone for Menu<'a, I, T> {
 #[derive(Clone)]
 enum PromptIterState {
     Newline,
+    /// A question from a resumable command, instead of the menu labels,
+    /// with this many parts printed so far
+    Question(usize),
     Menu(usize),
+    /// The index of a generated menu, with this many digits left to print
+    Index(usize, u32),
     Arrow,
     Done,
 }
@@ -273,6 +1104,7 @@ enum PromptIterState {
 struct PromptIter<'a, I, T> {
     menu_mgr: &'a MenuManager<'a, I, T>,
     state: PromptIterState,
+    question: Option<[&'a str; 3]>,
 }
 
 impl<I, T> Clone for PromptIter<'_, I, T> {
@@ -280,18 +1112,29 @@ impl<I, T> Clone for PromptIter<'_, I, T> {
         Self {
             menu_mgr: self.menu_mgr,
             state: self.state.clone(),
+            question: self.question,
         }
     }
 }
 
 impl<'a, I, T> PromptIter<'a, I, T> {
-    fn new(menu_mgr: &'a MenuManager<'a, I, T>, newline: bool) -> Self {
+    fn new(
+        menu_mgr: &'a MenuManager<'a, I, T>,
+        newline: bool,
+        question: Option<[&'a str; 3]>,
+    ) -> Self {
         let state = if newline {
             PromptIterState::Newline
+        } else if question.is_some() {
+            PromptIterState::Question(0)
         } else {
             Self::first_menu()
         };
-        Self { menu_mgr, state }
+        Self {
+            menu_mgr,
+            state,
+            question,
+        }
     }
 
     const fn first_menu() -> PromptIterState {
@@ -300,6 +1143,31 @@ impl<'a, I, T> PromptIter<'a, I, T> {
     }
 }
 
+/// A prompt drawn by a [`prompt::PromptFn`]
+struct CustomPrompt<'r, 'a, I, T> {
+    runner: &'r InnerRunner<'a, I, T>,
+    prompt_fn: prompt::PromptFn<T>,
+    context: &'r T,
+}
+
+impl<I, T> core::fmt::Display for CustomPrompt<'_, '_, I, T>
+where
+    I: embedded_io::Write,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.runner.draw_prompt(f, self.prompt_fn, self.context)
+    }
+}
+
+impl<I, T> core::fmt::Display for PromptIter<'_, I, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for part in self.clone() {
+            f.write_str(part)?;
+        }
+        Ok(())
+    }
+}
+
 impl<'a, I, T> Iterator for PromptIter<'a, I, T> {
     type Item = &'a str;
 
@@ -307,18 +1175,46 @@ impl<'a, I, T> Iterator for PromptIter<'a, I, T> {
         loop {
             match self.state {
                 PromptIterState::Newline => {
-                    self.state = Self::first_menu();
+                    self.state = if self.question.is_some() {
+                        PromptIterState::Question(0)
+                    } else {
+                        Self::first_menu()
+                    };
                     break Some("\n");
                 }
+                PromptIterState::Question(i) => {
+                    let part = self.question.and_then(|parts| parts.get(i).copied());
+                    self.state = match part {
+                        Some(_) => PromptIterState::Question(i + 1),
+                        None => PromptIterState::Done,
+                    };
+                    break part;
+                }
                 PromptIterState::Menu(i) => {
                     if i > self.menu_mgr.depth() {
                         self.state = PromptIterState::Arrow;
                     } else {
                         let menu = self.menu_mgr.get_menu(Some(i));
-                        self.state = PromptIterState::Menu(i + 1);
+                        self.state = match self.menu_mgr.generated_index(i) {
+                            Some(index) => {
+                                PromptIterState::Index(i, index.checked_ilog10().unwrap_or(0) + 1)
+                            }
+                            None => PromptIterState::Menu(i + 1),
+                        };
                         break Some(menu.label);
                     }
                 }
+                PromptIterState::Index(i, remaining) => {
+                    const DIGITS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+                    let index = self.menu_mgr.generated_index(i).unwrap_or(0);
+                    let digit = (index / 10usize.pow(remaining - 1)) % 10;
+                    self.state = if remaining > 1 {
+                        PromptIterState::Index(i, remaining - 1)
+                    } else {
+                        PromptIterState::Menu(i + 1)
+                    };
+                    break Some(DIGITS[digit]);
+                }
                 PromptIterState::Arrow => {
                     self.state = PromptIterState::Done;
                     break Some("> ");
@@ -347,11 +1243,206 @@ where
             interface,
             inner: InnerRunner {
                 menu_mgr: menu_manager::MenuManager::new(menu),
+                ignore_case: false,
+                dispatch_hidden: true,
+                repeat: false,
+                aliases: None,
+                #[cfg(feature = "variables")]
+                variables: None,
+                #[cfg(feature = "variables")]
+                have_variables: false,
+                last_status: Status::Success,
+                clock: None,
+                watch: None,
+                interrupt: None,
+                task: None,
+                session: session::Session::default(),
+                enable: None,
+                audit: None,
+                audit_id: 0,
+                #[cfg(feature = "style")]
+                theme: None,
+                prompt_fn: None,
+                help_width: None,
+                saved_line: [0; MAX_SAVED_LEN],
             },
         };
-        r.inner.prompt(&mut r.interface, true);
+        r.inner.prompt(&mut r.interface, context, true);
         r
     }
+
+    /// Match commands, sub-menu names, aliases and `--named` parameters
+    /// ignoring ASCII case, so `HELP` works as well as `help`.
+    ///
+    /// The names of `--named` arguments are given to callbacks as they were
+    /// declared, so [`argument_finder`] finds `--VERBOSE` as `verbose`.
+    /// Argument values are always passed exactly as typed.
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.inner.ignore_case = ignore_case;
+    }
+
+    /// Choose whether items which are not currently visible (see
+    /// [`Item::visibility`]) can still be run by typing their name. This is
+    /// `true` by default - hidden items are left out of `help` and Tab
+    /// completion, but still work.
+    pub fn set_dispatch_hidden(&mut self, dispatch_hidden: bool) {
+        self.inner.dispatch_hidden = dispatch_hidden;
+    }
+
+    /// Choose whether to have the `repeat` command, which runs another
+    /// command several times. This is `false` by default.
+    pub fn set_repeat(&mut self, repeat: bool) {
+        self.inner.repeat = repeat;
+    }
+
+    /// Give the runner somewhere to store aliases, which enables the `alias`
+    /// and `unalias` commands.
+    ///
+    /// An alias is expanded when it is the first word of a command, and any
+    /// other words are added to the end of the expansion. Aliases cannot
+    /// refer to other aliases.
+    pub fn set_aliases(&mut self, aliases: alias::Aliases<'a>) {
+        self.inner.aliases = Some(aliases);
+    }
+
+    /// Get the alias store, if there is one, so you can define aliases from
+    /// your own code.
+    pub fn aliases(&mut self) -> Option<&mut alias::Aliases<'a>> {
+        self.inner.aliases.as_mut()
+    }
+
+    /// Let a command which returned [`Status::Pending`] do some more work,
+    /// or run the command given to `watch` again when it is time.
+    ///
+    /// Call this regularly, such as from your main loop. When the command
+    /// finishes, the prompt is shown again. Until then, the only input
+    /// [`Runner::input_byte`] handles is Ctrl-C, which abandons the command
+    /// (any key stops `watch`).
+    ///
+    /// This is also when an [idle timeout](Runner::set_idle_timeout) is
+    /// noticed.
+    pub fn poll(&mut self, context: &mut T) {
+        if self.inner.timed_out(&mut self.interface, context) {
+            self.used = 0;
+            self.inner.prompt(&mut self.interface, context, true);
+            return;
+        }
+        let was_running = self.inner.is_running();
+        if self.inner.pop_removed_menus() && was_running {
+            // The command went with its menu
+            self.inner.prompt(&mut self.interface, context, true);
+            return;
+        }
+        if !self.inner.is_running() {
+            return;
+        }
+        self.inner.poll(&mut self.interface, context);
+        if !self.inner.is_running() {
+            self.inner.prompt(&mut self.interface, context, true);
+        }
+    }
+
+    /// Is there a command which returned [`Status::Pending`] and has not
+    /// finished yet?
+    pub fn is_running(&self) -> bool {
+        self.inner.is_running()
+    }
+
+    /// Give the runner a clock, for the idle timeout (see
+    /// [`Runner::set_idle_timeout`]) and for locking [gates](Menu::gate)
+    /// after too many wrong passwords.
+    ///
+    /// This doesn't enable the `watch` command - see
+    /// [`Runner::set_time_source`] for that.
+    pub fn set_clock(&mut self, clock: &'a mut dyn watch::TimeSource) {
+        self.inner.clock = Some(clock);
+    }
+
+    /// Log the operator out after `timeout_ms` milliseconds without any
+    /// input. This needs a clock - see [`Runner::set_clock`].
+    ///
+    /// When the time runs out, the runner leaves every sub-menu (calling
+    /// their exit functions), forgets any password given for a
+    /// [gate](Menu::gate), drops back to privilege level 0 and prints a
+    /// notice. Call [`Runner::poll`] regularly so this happens even when
+    /// nobody is typing. With `input_line`, the time is measured from one
+    /// line to the next, and a line typed after the time has run out is
+    /// thrown away.
+    pub fn set_idle_timeout(&mut self, timeout_ms: u32) {
+        self.inner.session.idle_timeout_ms = Some(timeout_ms);
+    }
+
+    /// Give the runner a function to call before and after each command
+    /// runs, so you can keep a record of what was done. See [`audit`].
+    pub fn set_audit(&mut self, audit: audit::AuditFn<T>) {
+        self.inner.audit = Some(audit);
+    }
+
+    /// Style the prompt, the headings in `help` and error messages with
+    /// this theme, or give `None` for plain text (which is the default).
+    ///
+    /// Only the prompt [`Runner::input_byte`] prints is styled, as `noline`
+    /// needs to know how wide its prompt is. See
+    /// [`Theme::if_supported`](style::Theme::if_supported) for leaving the
+    /// styling out on terminals which can't show it.
+    #[cfg(feature = "style")]
+    pub fn set_theme(&mut self, theme: Option<style::Theme>) {
+        self.inner.theme = theme;
+    }
+
+    /// Draw the prompt with your own function, in place of the labels of
+    /// the sub-menus. See [`prompt`].
+    pub fn set_prompt(&mut self, prompt_fn: prompt::PromptFn<T>) {
+        self.inner.prompt_fn = Some(prompt_fn);
+    }
+
+    /// Wrap the descriptions and parameter help printed by `help <command>`
+    /// to fit this many columns, or give `None` to print them exactly as
+    /// written (which is the default).
+    ///
+    /// Each paragraph is re-flowed, and the blank lines between paragraphs
+    /// are kept. A word which is too long for a line is left whole.
+    pub fn set_help_width(&mut self, width: Option<usize>) {
+        self.inner.help_width = width;
+    }
+
+    /// Give the runner a function which checks passwords for privilege
+    /// levels, which enables the `enable <level>` and `disable` commands.
+    ///
+    /// Items and menus with a [`privilege`](Item::privilege) above the
+    /// operator's level are left out of `help` and Tab completion, and can't
+    /// be used. Everyone starts at level 0.
+    pub fn set_enable(&mut self, enable: session::EnableFn<T>) {
+        self.inner.enable = Some(enable);
+    }
+
+    /// Give the runner an [`Interrupt`](interrupt::Interrupt), which lets
+    /// long-running commands be stopped.
+    ///
+    /// Pressing Ctrl-C at the prompt throws away the line, and if a command
+    /// is stopped by the interrupt the rest of the line is skipped.
+    pub fn set_interrupt(&mut self, interrupt: &'a interrupt::Interrupt) {
+        self.inner.interrupt = Some(interrupt);
+    }
+
+    /// Give the runner somewhere to store variables, which enables the `set`
+    /// and `unset` commands.
+    ///
+    /// Variables are substituted into each command before it runs, so
+    /// `$name` is replaced with the value of `name` and `$?` is `0` if the
+    /// last command worked or `1` if it failed.
+    #[cfg(feature = "variables")]
+    pub fn set_variables(&mut self, variables: variables::Variables<'a>) {
+        self.inner.variables = Some(variables);
+        self.inner.have_variables = true;
+    }
+
+    /// Get the variable store, if there is one, so you can read and set
+    /// variables from your own code.
+    #[cfg(feature = "variables")]
+    pub fn variables(&mut self) -> Option<&mut variables::Variables<'a>> {
+        self.inner.variables.as_mut()
+    }
 }
 
 #[cfg(feature = "noline")]
@@ -362,24 +1453,139 @@ where
     I: embedded_io::Read + embedded_io::Write,
 {
     pub fn input_line(&mut self, context: &mut T) -> Result<(), NolineError> {
-        let prompt = PromptIter::new(&self.inner.menu_mgr, false);
+        self.inner.pop_removed_menus();
+        // There's no way to read input without blocking, so wait for any
+        // running command to finish
+        while self.inner.is_running() {
+            self.poll(context);
+        }
 
-        let line = self.buffer.readline(prompt, &mut self.interface)?;
+        if self.inner.masked() {
+            return self.input_password(context);
+        }
+
+        let mut custom = [0u8; MAX_PROMPT_LEN];
+        let mut question = self.inner.question_prompt();
+        if let Some(prompt_fn) = self.inner.prompt_fn.filter(|_| question.is_none()) {
+            let mut out = PromptBuffer {
+                buffer: &mut custom,
+                used: 0,
+            };
+            // A prompt which is too long is cut short
+            let _ = self.inner.draw_prompt(&mut out, prompt_fn, context);
+            let len = out.used;
+            // We only ever copy in whole characters
+            question = Some([core::str::from_utf8(&custom[0..len]).unwrap(), "", ""]);
+        }
+        let prompt = PromptIter::new(&self.inner.menu_mgr, false, question);
+
+        let line = match self.buffer.readline(prompt, &mut self.interface) {
+            Ok(line) => line,
+            Err(e) => {
+                // Ctrl-C throws away any question we were asking
+                if matches!(e, NolineError::Aborted) {
+                    self.inner.cancel(&mut self.interface, context);
+                }
+                return Err(e);
+            }
+        };
+        if self.inner.timed_out(&mut self.interface, context) {
+            return Ok(());
+        }
+        self.inner.touch();
 
         #[cfg(not(feature = "echo"))]
         {
             // Echo the command
             write!(self.interface, "\r").unwrap();
-            write!(self.interface, "{}", line).unwrap();
+            self.inner.write_typed(&mut self.interface, line);
+        }
+
+        if self.inner.task.is_some() {
+            self.inner.answer(&mut self.interface, context, line);
+        } else {
+            self.inner
+                .process_command(&mut self.interface, context, line);
         }
 
-        self.inner
-            .process_command(&mut self.interface, context, line);
+        Ok(())
+    }
 
+    /// Read a password without showing it (or putting it in the history), and
+    /// give it to the command which asked for it.
+    fn input_password(&mut self, context: &mut T) -> Result<(), NolineError> {
+        let mut password = [0u8; MAX_PASSWORD_LEN];
+        let mut used = 0;
+        self.inner.prompt(&mut self.interface, context, false);
+        loop {
+            let mut byte = [0u8];
+            self.interface
+                .read_exact(&mut byte)
+                .map_err(|_| NolineError::IoError(embedded_io::ErrorKind::Other))?;
+            match byte[0] {
+                b'\r' | b'\n' => break,
+                interrupt::CTRL_C => {
+                    self.inner.cancel(&mut self.interface, context);
+                    return Err(NolineError::Aborted);
+                }
+                0x08 | 0x7F if used > 0 => {
+                    used -= 1;
+                    write!(self.interface, "\u{0008} \u{0008}").unwrap();
+                }
+                0x08 | 0x7F => {}
+                b if used < password.len() => {
+                    password[used] = b;
+                    used += 1;
+                    write!(self.interface, "*").unwrap();
+                }
+                _ => {}
+            }
+        }
+        if self.inner.timed_out(&mut self.interface, context) {
+            return Ok(());
+        }
+        self.inner.touch();
+        let line = core::str::from_utf8(&password[0..used]).unwrap_or("");
+        self.inner.answer(&mut self.interface, context, line);
         Ok(())
     }
 }
 
+/// The longest password [`Runner::input_line`] can read
+#[cfg(feature = "noline")]
+const MAX_PASSWORD_LEN: usize = 64;
+
+/// The longest prompt a [`prompt::PromptFn`] can draw for
+/// [`Runner::input_line`]
+#[cfg(feature = "noline")]
+const MAX_PROMPT_LEN: usize = 80;
+
+/// Collects a prompt drawn by a [`prompt::PromptFn`], for `noline`
+#[cfg(feature = "noline")]
+struct PromptBuffer<'b> {
+    buffer: &'b mut [u8],
+    used: usize,
+}
+
+#[cfg(feature = "noline")]
+impl core::fmt::Write for PromptBuffer<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let space = self.buffer.len() - self.used;
+        // Only copy whole characters
+        let len = (0..=s.len().min(space))
+            .rev()
+            .find(|i| s.is_char_boundary(*i))
+            .unwrap_or(0);
+        self.buffer[self.used..self.used + len].copy_from_slice(&s.as_bytes()[0..len]);
+        self.used += len;
+        if len < s.len() {
+            Err(core::fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 impl<I, T, B> Runner<'_, I, T, B>
 where
     I: embedded_io::Write,
@@ -394,6 +1600,23 @@ where
         if input == 0x0A {
             return;
         }
+        // In case nobody called `poll` when the idle timeout ran out
+        if self.inner.timed_out(&mut self.interface, context) {
+            self.used = 0;
+            self.inner.prompt(&mut self.interface, context, true);
+        }
+        self.inner.touch();
+        self.inner.pop_removed_menus();
+        // Any key stops `watch`
+        if self.inner.is_watching() {
+            self.inner.stop_watching();
+            self.inner.prompt(&mut self.interface, context, true);
+            return;
+        }
+        // Only Ctrl-C does anything until a running command finishes
+        if self.inner.is_running() && input != interrupt::CTRL_C {
+            return;
+        }
         let buffer = self.buffer.as_mut();
 
         let outcome = if input == 0x0D {
@@ -402,16 +1625,28 @@ where
                 {
                     // Echo the command
                     write!(self.interface, "\r").unwrap();
-                    write!(self.interface, "{}", line).unwrap();
+                    self.inner.write_typed(&mut self.interface, line);
+                }
+                // Handle the command, or the answer to a command's question
+                if self.inner.task.is_some() {
+                    self.inner.answer(&mut self.interface, context, line);
+                } else {
+                    self.inner
+                        .process_command(&mut self.interface, context, line);
                 }
-                // Handle the command
-                self.inner
-                    .process_command(&mut self.interface, context, line);
             } else {
                 // Hmm ..  we did not have a valid string
-                writeln!(self.interface, "Input was not valid UTF-8").unwrap();
+                self.inner.print_error(
+                    &mut self.interface,
+                    format_args!("Input was not valid UTF-8"),
+                );
             }
 
+            Outcome::CommandProcessed
+        } else if input == interrupt::CTRL_C {
+            // Throw away the line, and any command which hasn't finished
+            write!(self.interface, "^C").unwrap();
+            self.inner.cancel(&mut self.interface, context);
             Outcome::CommandProcessed
         } else if (input == 0x08) || (input == 0x7F) {
             // Handling backspace or delete
@@ -420,6 +1655,10 @@ where
                 self.used -= 1;
             }
             Outcome::NeedMore
+        } else if input == 0x09 {
+            // Tab - try and complete the command
+            self.complete_command(context);
+            Outcome::NeedMore
         } else if self.used < buffer.len() {
             buffer[self.used] = input;
             self.used += 1;
@@ -434,369 +1673,3634 @@ where
                 // Now we've released the buffer, we can draw the prompt
                 if valid {
                     write!(self.interface, "\r").unwrap();
-                    self.inner.prompt(&mut self.interface, false);
+                    self.inner.prompt(&mut self.interface, context, false);
                 }
                 // Grab the buffer again to render it to the screen
                 if let Ok(s) = core::str::from_utf8(&buffer[0..self.used]) {
-                    write!(self.interface, "{}", s).unwrap();
+                    self.inner.write_typed(&mut self.interface, s);
                 }
             }
             Outcome::NeedMore
         } else {
-            writeln!(self.interface, "Buffer overflow!").unwrap();
+            self.inner
+                .print_error(&mut self.interface, format_args!("Buffer overflow!"));
             Outcome::NeedMore
         };
         match outcome {
             Outcome::CommandProcessed => {
                 self.used = 0;
-                self.inner.prompt(&mut self.interface, true);
+                self.inner.prompt(&mut self.interface, context, true);
             }
             Outcome::NeedMore => {}
         }
     }
+
+    /// Complete the command word in the buffer. If there is exactly one
+    /// candidate it is filled in, otherwise the candidates are listed.
+    fn complete_command(&mut self, context: &T) {
+        let buffer = self.buffer.as_mut();
+        let Ok(partial) = core::str::from_utf8(&buffer[0..self.used]) else {
+            return;
+        };
+        if partial.contains(char::is_whitespace) {
+            // We only complete the first word
+            return;
+        }
+        let mut count = 0;
+        let mut candidate = "";
+        self.inner.for_each_completion(partial, context, |name| {
+            count += 1;
+            candidate = name;
+        });
+        match count {
+            0 => {}
+            1 => {
+                let suffix = &candidate.as_bytes()[partial.len()..];
+                // Generated sub-menus need an index typing straight after
+                let space: &[u8] = if self.inner.needs_index(candidate) {
+                    b""
+                } else {
+                    b" "
+                };
+                let end = self.used + suffix.len() + space.len();
+                if end <= buffer.len() {
+                    buffer[self.used..self.used + suffix.len()].copy_from_slice(suffix);
+                    buffer[self.used + suffix.len()..end].copy_from_slice(space);
+                    self.used = end;
+                    #[cfg(feature = "echo")]
+                    {
+                        self.interface.write_all(suffix).unwrap();
+                        self.interface.write_all(space).unwrap();
+                    }
+                }
+            }
+            _ => {
+                writeln!(self.interface).unwrap();
+                self.inner.for_each_completion(partial, context, |name| {
+                    write!(self.interface, "  {}", name).unwrap();
+                });
+                writeln!(self.interface).unwrap();
+                self.inner.prompt(&mut self.interface, context, false);
+                write!(self.interface, "{}", partial).unwrap();
+            }
+        }
+    }
 }
 
-impl<I, T> InnerRunner<'_, I, T>
+impl<'a, I, T> InnerRunner<'a, I, T>
 where
     I: embedded_io::Write,
 {
     /// Print out a new command prompt, including sub-menu names if
     /// applicable.
-    pub fn prompt(&mut self, interface: &mut I, newline: bool) {
-        let prompt = PromptIter::new(&self.menu_mgr, newline);
+    pub fn prompt(&mut self, interface: &mut I, context: &T, newline: bool) {
+        if self.is_running() {
+            // No prompt until the command finishes
+            return;
+        }
+        if newline {
+            writeln!(interface).unwrap();
+        }
+        let question = self.question_prompt();
+        match self.prompt_fn.filter(|_| question.is_none()) {
+            Some(prompt_fn) => {
+                let prompt = CustomPrompt {
+                    runner: self,
+                    prompt_fn,
+                    context,
+                };
+                self.write_styled(interface, Style::Prompt, format_args!("{}", prompt));
+            }
+            None => {
+                let prompt = PromptIter::new(&self.menu_mgr, false, question);
+                self.write_styled(interface, Style::Prompt, format_args!("{}", prompt));
+            }
+        }
+    }
 
-        for part in prompt {
-            write!(interface, "{}", part).unwrap();
+    /// Give the prompt function what it needs to draw the prompt.
+    fn draw_prompt(
+        &self,
+        out: &mut dyn core::fmt::Write,
+        prompt_fn: prompt::PromptFn<T>,
+        context: &T,
+    ) -> core::fmt::Result {
+        let (path, depth) = self.menu_path();
+        let info = prompt::PromptInfo {
+            path: &path[0..depth],
+            privilege: self.session.level,
+            last_status: self.last_status,
+        };
+        prompt_fn(out, &info, context)
+    }
+
+    /// The sub-menus the operator is in, from the top: the label of each, and
+    /// the index it was generated with. Only the first `depth` are used.
+    fn menu_path(&self) -> ([(&'a str, Option<usize>); 4], usize) {
+        let mut path = [("", None); 4];
+        let depth = self.menu_mgr.depth();
+        for (level, part) in path.iter_mut().enumerate().take(depth) {
+            let label = self.menu_mgr.get_menu(Some(level + 1)).label;
+            *part = (label, self.menu_mgr.generated_index(level + 1));
+        }
+        (path, depth)
+    }
+
+    /// Write out what the operator typed, with any password, or the value of
+    /// any secret argument, shown as `*`s.
+    fn write_typed(&self, interface: &mut I, line: &str) {
+        if self.masked() {
+            for _ in line.chars() {
+                write!(interface, "*").unwrap();
+            }
+            return;
+        }
+        if self.task.is_some() {
+            write!(interface, "{}", line).unwrap();
+            return;
+        }
+        let mut remaining = line;
+        loop {
+            let (command, next) = next_command(remaining);
+            self.write_command(interface, command);
+            let Some((separator, rest)) = next else {
+                break;
+            };
+            match separator {
+                Separator::Then => write!(interface, ";").unwrap(),
+                Separator::And => write!(interface, "&&").unwrap(),
+            }
+            remaining = rest;
+        }
+    }
+
+    /// Write out one command, with the value of any secret argument shown as
+    /// `*`s.
+    fn write_command(&self, interface: &mut I, command: &str) {
+        let menu = self.menu_mgr.get_menu(None);
+        let item = command
+            .split_whitespace()
+            .next()
+            .and_then(|cmd| menu.all_items().find(|i| self.match_item(cmd, i).is_some()));
+        let Some((item, parameters)) = item.and_then(|item| match item.item_type {
+            ItemType::Callback { parameters, .. } | ItemType::Resumable { parameters, .. } => {
+                Some((item, parameters))
+            }
+            _ => None,
+        }) else {
+            write!(interface, "{}", command).unwrap();
+            return;
+        };
+        let mut secrets = SecretArgs::new(parameters, self.ignore_case);
+        let mut rest = command;
+        let mut first = true;
+        while !rest.is_empty() {
+            let start = rest
+                .find(|c: char| !c.is_whitespace())
+                .unwrap_or(rest.len());
+            let end = rest[start..]
+                .find(char::is_whitespace)
+                .map_or(rest.len(), |len| start + len);
+            let word = &rest[start..end];
+            write!(interface, "{}", &rest[0..start]).unwrap();
+            let skip = first || (item.confirm && is_yes_flag(word));
+            if !skip && secrets.is_secret(word) {
+                // Keep the name of a `--name=value` argument
+                let value_start = word.find('=').map_or(0, |pos| pos + 1);
+                write!(interface, "{}", &word[0..value_start]).unwrap();
+                for _ in word[value_start..].chars() {
+                    write!(interface, "*").unwrap();
+                }
+            } else {
+                write!(interface, "{}", word).unwrap();
+            }
+            first = false;
+            rest = &rest[end..];
+        }
+    }
+
+    /// Explain why variables could not be substituted into a command.
+    #[cfg(feature = "variables")]
+    fn print_expand_error(&self, interface: &mut I, error: variables::ExpandError) {
+        match error {
+            variables::ExpandError::Unknown(name) => {
+                self.print_error(
+                    interface,
+                    format_args!("Error: No such variable {:?}", name),
+                );
+            }
+            variables::ExpandError::NoRoom => {
+                self.print_error(
+                    interface,
+                    format_args!("Error: No room to substitute variables"),
+                );
+            }
+        }
+    }
+
+    /// Write some text, in a style from the theme if there is one.
+    fn write_styled(&self, interface: &mut I, kind: Style, text: core::fmt::Arguments) {
+        #[cfg(feature = "style")]
+        if let Some(theme) = self.theme {
+            let code = match kind {
+                Style::Prompt => theme.prompt,
+                Style::Heading => theme.heading,
+                Style::Error => theme.error,
+            };
+            write!(interface, "{}{}{}", code, text, style::RESET).unwrap();
+            return;
+        }
+        #[cfg(not(feature = "style"))]
+        let _ = kind;
+        write!(interface, "{}", text).unwrap();
+    }
+
+    /// Print an error message, on a line of its own.
+    fn print_error(&self, interface: &mut I, message: core::fmt::Arguments) {
+        self.write_styled(interface, Style::Error, message);
+        writeln!(interface).unwrap();
+    }
+
+    /// Print a heading in `help`, on a line of its own.
+    fn print_heading(&self, interface: &mut I, heading: &str) {
+        self.write_styled(interface, Style::Heading, format_args!("{}", heading));
+        writeln!(interface).unwrap();
+    }
+
+    /// Does the word the user typed match this command name?
+    fn matches(&self, typed: &str, name: &str) -> bool {
+        matches_word(typed, name, self.ignore_case)
+    }
+
+    /// Does the word the user typed match this item's command or one of its
+    /// aliases?
+    ///
+    /// Returns `None` if not. For a generated sub-menu, returns the index
+    /// typed after the command, otherwise `Some(None)`.
+    fn match_item(&self, typed: &str, item: &Item<I, T>) -> Option<Option<usize>> {
+        match item.item_type {
+            ItemType::Generated { count, .. } => item
+                .names()
+                .find_map(|name| {
+                    let prefix = typed.get(0..name.len())?;
+                    let digits = &typed[name.len()..];
+                    // Only one way to type each index, so no `ch010`
+                    if !self.matches(prefix, name)
+                        || digits.is_empty()
+                        || !digits.bytes().all(|b| b.is_ascii_digit())
+                        || (digits.len() > 1 && digits.starts_with('0'))
+                    {
+                        return None;
+                    }
+                    digits.parse().ok().filter(|index| *index < count)
+                })
+                .map(Some),
+            _ => item
+                .names()
+                .any(|name| self.matches(typed, name))
+                .then_some(None),
+        }
+    }
+
+    /// Is `help typed` about this item? The command of a generated sub-menu
+    /// describes the whole family, with or without an index.
+    fn match_help(&self, typed: &str, item: &Item<I, T>) -> bool {
+        self.match_item(typed, item).is_some()
+            || (matches!(item.item_type, ItemType::Generated { .. })
+                && item.names().any(|name| self.matches(typed, name)))
+    }
+
+    /// Is this word the command of a generated sub-menu, and so needs an
+    /// index typing after it?
+    fn needs_index(&self, word: &str) -> bool {
+        self.menu_mgr.get_menu(None).all_items().any(|item| {
+            matches!(item.item_type, ItemType::Generated { .. }) && item.names().any(|n| n == word)
+        })
+    }
+
+    /// Every visible command word which could be typed at the prompt.
+    fn words<'b>(&self, context: &'b T) -> impl Iterator<Item = &'a str> + Clone + 'b
+    where
+        'a: 'b,
+    {
+        let items = self.menu_mgr.get_menu(None).all_items();
+        let level = self.session.level;
+        items
+            .filter(move |item| item.is_visible(context) && item.is_allowed(level))
+            .flat_map(|item| item.names())
+            .chain(self.builtins(context).map(|(word, _, _)| word))
+    }
+
+    /// The built-in commands which can be used at the moment. Those with the
+    /// same name as an item in the current menu are left out, as the item
+    /// is run instead.
+    fn builtins<'w>(
+        &self,
+        context: &T,
+    ) -> impl Iterator<Item = (&'w str, &'w str, &'w str)> + Clone {
+        #[cfg(feature = "variables")]
+        let have_variables = self.have_variables;
+        #[cfg(not(feature = "variables"))]
+        let have_variables = false;
+        let in_sub_menu = self.menu_mgr.depth() != 0;
+        let have_aliases = self.aliases.is_some();
+        let have_repeat = self.repeat;
+        let have_watch = self.watch.is_some();
+        let logged_in = self.session.logged_in();
+        let have_enable = self.enable.is_some();
+        let privileged = self.session.level != 0;
+        let table: [(&'w str, &'w str, &'w str); 11] = BUILTINS;
+        let hidden = table.map(|(word, _, _)| self.find_item(context, word).is_some());
+        table
+            .into_iter()
+            .zip(hidden)
+            .filter(move |((word, _, _), hidden)| {
+                !hidden
+                    && match *word {
+                        "exit" => in_sub_menu,
+                        "alias" | "unalias" => have_aliases,
+                        "set" | "unset" => have_variables,
+                        "repeat" => have_repeat,
+                        "watch" => have_watch,
+                        "logout" => logged_in,
+                        "enable" => have_enable,
+                        "disable" => privileged,
+                        _ => true,
+                    }
+            })
+            .map(|(builtin, _)| builtin)
+    }
+
+    /// Find the item in the current menu which `cmd` runs, if the operator
+    /// can use it. Returns its index, and for a generated sub-menu the index
+    /// typed after the command.
+    fn find_item(
+        &self,
+        context: &T,
+        cmd: &str,
+    ) -> Option<(usize, &'a Item<'a, I, T>, Option<usize>)> {
+        let level = self.session.level;
+        self.menu_mgr
+            .get_menu(None)
+            .indexed_items()
+            .find_map(|(i, item)| {
+                let generated_index = self.match_item(cmd, item)?;
+                let usable =
+                    (self.dispatch_hidden || item.is_visible(context)) && item.is_allowed(level);
+                usable.then_some((i, item, generated_index))
+            })
+    }
+
+    /// Call `f` with every word which could be typed at the prompt, and which
+    /// starts with `partial`.
+    fn for_each_completion(&self, partial: &str, context: &T, mut f: impl FnMut(&'a str)) {
+        for name in self.words(context) {
+            if name
+                .get(0..partial.len())
+                .is_some_and(|prefix| self.matches(partial, prefix))
+            {
+                f(name);
+            }
         }
     }
 
     /// Scan the buffer and do the right thing based on its contents.
-    fn process_command(&mut self, interface: &mut I, context: &mut T, command_line: &str) {
+    fn process_command(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        command_line: &str,
+    ) -> Status {
         // Go to the next line, below the prompt
         writeln!(interface).unwrap();
-        // We have a valid string
+        if command_line.trim().is_empty() {
+            self.print_error(interface, format_args!("Input was empty?"));
+            return Status::Success;
+        }
+        // Forget any old request to stop
+        self.take_interrupt();
+        let status = self.run_line(interface, context, command_line);
+        if self.take_interrupt() {
+            writeln!(interface, "^C").unwrap();
+            return Status::Failure;
+        }
+        status
+    }
+
+    /// Has the running command been asked to stop?
+    fn interrupt_requested(&self) -> bool {
+        self.interrupt.is_some_and(|i| i.is_requested())
+    }
+
+    /// Say whether the running command was asked to stop, and forget the
+    /// request.
+    fn take_interrupt(&self) -> bool {
+        self.interrupt.is_some_and(|i| i.take())
+    }
+
+    /// Run every command on a line.
+    ///
+    /// The line may hold several commands separated by `;` (run the next
+    /// command regardless) or `&&` (only run the next command if this one
+    /// worked). Returns the status of the last command which was run.
+    ///
+    /// If a command asks a question or keeps running, the rest of the line
+    /// is not run - an error says which commands were skipped.
+    fn run_line(&mut self, interface: &mut I, context: &mut T, command_line: &str) -> Status {
+        let mut status = Status::Success;
+        let mut remaining = command_line;
+        let mut skip = false;
+        loop {
+            let (command, mut next) = next_command(remaining);
+            if !skip && !command.trim().is_empty() {
+                status = self.run_alias_or_command(interface, context, command);
+                if self.interrupt_requested() {
+                    // Skip everything else on the line
+                    status = Status::Failure;
+                    next = None;
+                } else if self.task.is_some() {
+                    // Skip everything else, while we wait for an answer
+                    if let Some((_, rest)) = next.take() {
+                        self.print_error(
+                            interface,
+                            format_args!(
+                                "Error: Skipped {:?} as {:?} has not finished",
+                                rest.trim(),
+                                command.trim()
+                            ),
+                        );
+                    }
+                }
+                self.last_status = status;
+            }
+            let Some((separator, rest)) = next else {
+                break;
+            };
+            skip = separator == Separator::And && status == Status::Failure;
+            remaining = rest;
+        }
+        status
+    }
+
+    /// Run a single command, expanding it first if it is an alias.
+    fn run_alias_or_command(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        command_line: &str,
+    ) -> Status {
+        let command_line = command_line.trim_start();
+        let cmd = command_line.split_whitespace().next().unwrap_or("");
+        // Take the aliases out while the expansion runs, so it can't change
+        // them (or expand another alias).
+        let Some(mut aliases) = self.aliases.take() else {
+            return self.run_command(interface, context, command_line);
+        };
+        let args = command_line[cmd.len()..].trim();
+        let status = match aliases.expand(cmd, args, self.ignore_case) {
+            Some(Ok(expansion)) => self.run_line(interface, context, expansion),
+            Some(Err(_)) => {
+                self.print_error(
+                    interface,
+                    format_args!("Error: No room to expand alias {:?}", cmd),
+                );
+                Status::Failure
+            }
+            None => {
+                self.aliases = Some(aliases);
+                return self.run_command(interface, context, command_line);
+            }
+        };
+        self.aliases = Some(aliases);
+        status
+    }
+
+    /// Handle the `alias` and `unalias` commands.
+    fn run_alias_builtin(&mut self, interface: &mut I, command_line: &str) -> Status {
         let mut parts = command_line.split_whitespace();
-        if let Some(cmd) = parts.next() {
-            let menu = self.menu_mgr.get_menu(None);
-            if cmd == "help" {
-                match parts.next() {
-                    Some(arg) => match menu.items.iter().find(|i| i.command == arg) {
-                        Some(item) => {
-                            self.print_long_help(interface, item);
-                        }
-                        None => {
-                            writeln!(interface, "I can't help with {:?}", arg).unwrap();
-                        }
-                    },
-                    _ => {
-                        writeln!(interface, "AVAILABLE ITEMS:").unwrap();
-                        for item in menu.items {
-                            self.print_short_help(interface, item);
-                        }
-                        if self.menu_mgr.depth() != 0 {
-                            self.print_short_help(
-                                interface,
-                                &Item {
-                                    command: "exit",
-                                    help: Some("Leave this menu."),
-                                    item_type: ItemType::_Dummy,
-                                },
-                            );
-                        }
-                        self.print_short_help(
+        let cmd = parts.next().unwrap_or("");
+        let name = parts.next();
+        let Some(aliases) = self.aliases.as_mut() else {
+            return Status::Failure;
+        };
+        if matches_word(cmd, "unalias", self.ignore_case) {
+            let Some(name) = name else {
+                self.print_error(
+                    interface,
+                    format_args!("Error: Insufficient arguments given"),
+                );
+                return Status::Failure;
+            };
+            if !aliases.remove(name) {
+                self.print_error(interface, format_args!("Error: No such alias {:?}", name));
+                return Status::Failure;
+            }
+            return Status::Success;
+        }
+        let Some(name) = name else {
+            for (name, expansion) in aliases.iter() {
+                writeln!(interface, "alias {} \"{}\"", name, expansion).unwrap();
+            }
+            return Status::Success;
+        };
+        // Everything after the name, without any quotes around it
+        let rest = command_line.trim_start()[cmd.len()..].trim_start()[name.len()..].trim();
+        if rest.is_empty() {
+            return match aliases.get(name) {
+                Some(expansion) => {
+                    writeln!(interface, "alias {} \"{}\"", name, expansion).unwrap();
+                    Status::Success
+                }
+                None => {
+                    self.print_error(interface, format_args!("Error: No such alias {:?}", name));
+                    Status::Failure
+                }
+            };
+        }
+        let expansion = rest
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or(rest);
+        match aliases.define(name, expansion) {
+            Ok(()) => Status::Success,
+            Err(Error::InvalidName) => {
+                self.print_error(
+                    interface,
+                    format_args!("Error: {:?} can't be used as an alias", name),
+                );
+                Status::Failure
+            }
+            Err(_) => {
+                self.print_error(
+                    interface,
+                    format_args!("Error: No room for alias {:?}", name),
+                );
+                Status::Failure
+            }
+        }
+    }
+
+    /// Substitute variables into a command and then run it, or handle the
+    /// `set` and `unset` commands.
+    #[cfg(feature = "variables")]
+    fn run_with_variables(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        variables: &mut variables::Variables<'a>,
+        command_line: &str,
+    ) -> Status {
+        let mut parts = command_line.split_whitespace();
+        let cmd = parts.next().unwrap_or("");
+        // Items of your own win over `set` and `unset`
+        let builtin = self.find_item(context, cmd).is_none();
+        if builtin && self.matches(cmd, "unset") {
+            let Some(name) = parts.next() else {
+                self.print_error(
+                    interface,
+                    format_args!("Error: Insufficient arguments given"),
+                );
+                return Status::Failure;
+            };
+            if !variables.remove(name) {
+                self.print_error(
+                    interface,
+                    format_args!("Error: No such variable {:?}", name),
+                );
+                return Status::Failure;
+            }
+            return Status::Success;
+        }
+        if !(builtin && self.matches(cmd, "set")) {
+            return match variables.expand(command_line, self.last_status) {
+                Ok(line) => self.run_command(interface, context, line),
+                Err(e) => {
+                    self.print_expand_error(interface, e);
+                    Status::Failure
+                }
+            };
+        }
+        let Some(name) = parts.next() else {
+            for (name, value) in variables.iter() {
+                writeln!(interface, "{}=\"{}\"", name, value).unwrap();
+            }
+            return Status::Success;
+        };
+        // Everything after the name, without any quotes around it
+        let rest = command_line.trim_start()[cmd.len()..].trim_start()[name.len()..].trim();
+        if rest.is_empty() {
+            return match variables.get(name) {
+                Some(value) => {
+                    writeln!(interface, "{}=\"{}\"", name, value).unwrap();
+                    Status::Success
+                }
+                None => {
+                    self.print_error(
+                        interface,
+                        format_args!("Error: No such variable {:?}", name),
+                    );
+                    Status::Failure
+                }
+            };
+        }
+        if !variables::is_valid_name(name) {
+            self.print_error(
+                interface,
+                format_args!("Error: Bad variable name {:?}", name),
+            );
+            return Status::Failure;
+        }
+        let value = rest
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or(rest);
+        match variables.set_expanded(name, value, self.last_status) {
+            Ok(()) => Status::Success,
+            Err(e) => {
+                self.print_expand_error(interface, e);
+                Status::Failure
+            }
+        }
+    }
+
+    /// Handle the `repeat` and `watch` commands, which run another command
+    /// over and over.
+    fn run_repeat_builtin(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        command_line: &str,
+    ) -> Status {
+        let command_line = command_line.trim_start();
+        let mut parts = command_line.split_whitespace();
+        let cmd = parts.next().unwrap_or("");
+        let Some(number_str) = parts.next() else {
+            self.print_error(
+                interface,
+                format_args!("Error: Insufficient arguments given"),
+            );
+            return Status::Failure;
+        };
+        let Ok(number) = number_str.parse::<u32>() else {
+            self.print_error(
+                interface,
+                format_args!("Error: {:?} is not a number", number_str),
+            );
+            return Status::Failure;
+        };
+        let command = command_line[cmd.len()..].trim_start()[number_str.len()..].trim();
+        if command.is_empty() {
+            self.print_error(
+                interface,
+                format_args!("Error: Insufficient arguments given"),
+            );
+            return Status::Failure;
+        }
+        if self.matches(cmd, "repeat") {
+            for done in 1..=number {
+                let status = self.run_alias_or_command(interface, context, command);
+                if status == Status::Failure || self.interrupt_requested() {
+                    return Status::Failure;
+                }
+                if self.task.is_some() {
+                    if done < number {
+                        self.print_error(
                             interface,
-                            &Item {
-                                command: "help [ <command> ]",
-                                help: Some("Show this help, or get help on a specific command."),
-                                item_type: ItemType::_Dummy,
-                            },
+                            format_args!(
+                                "Error: Skipped {} more runs as {:?} has not finished",
+                                number - done,
+                                command
+                            ),
                         );
                     }
-                }
-            } else if cmd == "exit" && self.menu_mgr.depth() != 0 {
-                if let Some(cb_fn) = menu.exit {
-                    cb_fn(menu, interface, context);
-                }
-                self.menu_mgr.pop_menu();
-            } else {
-                let mut found = false;
-                for (i, item) in menu.items.iter().enumerate() {
-                    if cmd == item.command {
-                        match item.item_type {
-                            ItemType::Callback {
-                                function,
-                                parameters,
-                            } => Self::call_function(
-                                interface,
-                                context,
-                                function,
-                                parameters,
-                                menu,
-                                item,
-                                command_line,
-                            ),
-                            ItemType::Menu(incoming_menu) => {
-                                if let Some(cb_fn) = incoming_menu.entry {
-                                    cb_fn(incoming_menu, interface, context);
-                                }
-                                self.menu_mgr.push_menu(i);
-                            }
-                            ItemType::_Dummy => {
-                                unreachable!();
-                            }
-                        }
-                        found = true;
-                        break;
+                    return status;
+                }
+            }
+            return Status::Success;
+        }
+        let Some(saved) = self.saved_line.get_mut(0..command.len()) else {
+            self.print_error(interface, format_args!("Error: Command too long to watch"));
+            return Status::Failure;
+        };
+        saved.copy_from_slice(command.as_bytes());
+        writeln!(
+            interface,
+            "Every {}ms: {} (press any key to stop)",
+            number, command
+        )
+        .unwrap();
+        self.run_watched(interface, context, command.len(), number)
+    }
+
+    /// Run the command line `watch` was given, which is saved in
+    /// `saved_line`, then wait to run it again.
+    fn run_watched(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        len: usize,
+        period_ms: u32,
+    ) -> Status {
+        // Copy the command line out, as the command may save another one
+        let mut line = [0u8; MAX_SAVED_LEN];
+        line[0..len].copy_from_slice(&self.saved_line[0..len]);
+        // We only ever save whole strings
+        let command = core::str::from_utf8(&line[0..len]).unwrap();
+        // Take the watch out while the command runs, so it can't start
+        // another watch.
+        let watch = self.watch.take();
+        let status = self.run_alias_or_command(interface, context, command);
+        self.watch = watch;
+        self.last_status = status;
+        if self.interrupt_requested() {
+            return Status::Failure;
+        }
+        if self.task.is_some() {
+            self.print_error(
+                interface,
+                format_args!("Error: Stopped watching as {:?} has not finished", command),
+            );
+            return status;
+        }
+        // There is always a clock when there is a watch
+        let last_ms = self.now_ms().unwrap_or(0);
+        self.task = Some(Task {
+            index: 0,
+            state: TaskState::Watch {
+                len,
+                period_ms,
+                last_ms,
+                status,
+            },
+        });
+        Status::Pending
+    }
+
+    /// Stop `watch` if a key has been pressed, or run its command again if
+    /// it is time.
+    fn poll_watch(&mut self, interface: &mut I, context: &mut T) -> Status {
+        let Some(Task {
+            state:
+                TaskState::Watch {
+                    len,
+                    period_ms,
+                    last_ms,
+                    ..
+                },
+            ..
+        }) = self.task
+        else {
+            return Status::Failure;
+        };
+        if self
+            .watch
+            .as_ref()
+            .is_some_and(|watch| (watch.key_pressed)(interface))
+        {
+            return self.stop_watching();
+        }
+        if self.now_ms().unwrap_or(0).wrapping_sub(last_ms) < period_ms {
+            return Status::Pending;
+        }
+        self.task = None;
+        self.run_watched(interface, context, len, period_ms)
+    }
+
+    /// Stop `watch`, which finishes with the status the command returned
+    /// when it last ran.
+    fn stop_watching(&mut self) -> Status {
+        if let Some(Task {
+            state: TaskState::Watch { status, .. },
+            ..
+        }) = self.task.take()
+        {
+            self.last_status = status;
+        }
+        self.last_status
+    }
+
+    /// Run a single command.
+    fn run_command(&mut self, interface: &mut I, context: &mut T, command_line: &str) -> Status {
+        // The last command may have removed the menu we were in
+        self.pop_removed_menus();
+        // Take the variables out while the command runs. The expanded command
+        // is kept in their spare space.
+        #[cfg(feature = "variables")]
+        if let Some(mut variables) = self.variables.take() {
+            let status = self.run_with_variables(interface, context, &mut variables, command_line);
+            self.variables = Some(variables);
+            return status;
+        }
+        let mut parts = command_line.split_whitespace();
+        let Some(cmd) = parts.next() else {
+            return Status::Success;
+        };
+        self.audit_id = self.audit_id.wrapping_add(1);
+        let menu = self.menu_mgr.get_menu(None);
+        // Items of your own win over built-in commands with the same name
+        let found = self.find_item(context, cmd);
+        if found.is_none() {
+            if let Some(status) =
+                self.run_builtin(interface, context, &menu, cmd, parts, command_line)
+            {
+                return status;
+            }
+        }
+        let Some((i, item, generated_index)) = found else {
+            self.print_error(
+                interface,
+                format_args!("Command {:?} not found. Try 'help'.", cmd),
+            );
+            suggest(interface, cmd, "", self.words(context), self.ignore_case);
+            return Status::Failure;
+        };
+        if let Err(reason) = item.check_enabled(context) {
+            self.print_error(interface, format_args!("Error: not available: {}", reason));
+            self.audit_command(
+                context,
+                item.command,
+                &[],
+                audit::Stage::Rejected("not available"),
+            );
+            return Status::Failure;
+        }
+        match item.item_type {
+            ItemType::Callback { .. } | ItemType::Resumable { .. } => {
+                if item.confirm && !command_line.split_whitespace().any(is_yes_flag) {
+                    return self.ask_to_confirm(interface, context, i, command_line);
+                }
+                let status = self.call_function(interface, context, &menu, item, command_line);
+                self.suspend(interface, i, item, status)
+            }
+            ItemType::Menu(_) | ItemType::Generated { .. } => {
+                self.enter_menu(interface, context, i, generated_index, false)
+            }
+            ItemType::_Dummy => {
+                unreachable!();
+            }
+        }
+    }
+
+    /// Run `cmd` if it is a built-in command which can be used at the
+    /// moment. Returns `None` if it isn't one.
+    fn run_builtin(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        menu: &Menu<'a, I, T>,
+        cmd: &str,
+        mut parts: core::str::SplitWhitespace,
+        command_line: &str,
+    ) -> Option<Status> {
+        let level = self.session.level;
+        if self.matches(cmd, "help") {
+            match parts.next() {
+                Some(arg) => match menu.all_items().find(|i| {
+                    i.is_visible(context) && i.is_allowed(level) && self.match_help(arg, i)
+                }) {
+                    Some(item) => {
+                        self.print_long_help(interface, item);
+                    }
+                    None => {
+                        self.print_error(interface, format_args!("I can't help with {:?}", arg));
+                        return Some(Status::Failure);
+                    }
+                },
+                _ => {
+                    self.print_heading(interface, "AVAILABLE ITEMS:");
+                    for item in menu
+                        .all_items()
+                        .filter(|i| i.is_visible(context) && i.is_allowed(level))
+                    {
+                        self.print_short_help(interface, item, item.check_enabled(context));
+                    }
+                    for (_, command, help) in self.builtins(context) {
+                        self.print_short_help(
+                            interface,
+                            &Item::new(command, ItemType::_Dummy).help(help),
+                            Ok(()),
+                        );
+                    }
+                }
+            }
+            return Some(Status::Success);
+        }
+        if self.aliases.is_some() && (self.matches(cmd, "alias") || self.matches(cmd, "unalias")) {
+            return Some(self.run_alias_builtin(interface, command_line));
+        }
+        if (self.repeat && self.matches(cmd, "repeat"))
+            || (self.watch.is_some() && self.matches(cmd, "watch"))
+        {
+            return Some(self.run_repeat_builtin(interface, context, command_line));
+        }
+        if self.enable.is_some() && self.matches(cmd, "enable") {
+            return Some(self.run_enable_builtin(interface, context, parts.next()));
+        }
+        if self.session.level != 0 && self.matches(cmd, "disable") {
+            self.set_level(interface, context, 0);
+            self.audit_command(
+                context,
+                "disable",
+                &[],
+                audit::Stage::After(Status::Success),
+            );
+            return Some(Status::Success);
+        }
+        if self.session.logged_in() && self.matches(cmd, "logout") {
+            self.logout(interface, context);
+            return Some(Status::Success);
+        }
+        if self.matches(cmd, "exit") && self.menu_mgr.depth() != 0 {
+            if let Some(cb_fn) = menu.exit {
+                cb_fn(menu, interface, context);
+            }
+            self.menu_mgr.pop_menu();
+            return Some(Status::Success);
+        }
+        None
+    }
+
+    /// Enter the sub-menu at `index` in the current menu, asking for a
+    /// password first if it has a gate (unless `checked` says we just did).
+    fn enter_menu(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        index: usize,
+        generated_index: Option<usize>,
+        checked: bool,
+    ) -> Status {
+        let Some(item) = self.menu_mgr.get_menu(None).item(index) else {
+            return Status::Failure;
+        };
+        let incoming_menu = match item.item_type {
+            ItemType::Menu(menu) => menu.clone(),
+            ItemType::Generated { generator, .. } => Menu {
+                index: generated_index,
+                ..generator(generated_index.unwrap_or(0))
+            },
+            _ => return Status::Failure,
+        };
+        if incoming_menu.privilege > self.session.level {
+            self.print_error(interface, format_args!("Error: Permission denied"));
+            self.audit_command(
+                context,
+                item.command,
+                &[],
+                audit::Stage::Rejected("permission denied"),
+            );
+            return Status::Failure;
+        }
+        if let Some(gate) = incoming_menu
+            .gate
+            .filter(|gate| !checked && !self.session.has_passed(gate))
+        {
+            let now_ms = self.now_ms();
+            if self.session.is_locked(gate, now_ms) {
+                self.print_error(
+                    interface,
+                    format_args!("Error: Too many wrong passwords. Try again later."),
+                );
+                self.audit_command(
+                    context,
+                    item.command,
+                    &[],
+                    audit::Stage::Rejected("locked out"),
+                );
+                return Status::Failure;
+            }
+            let question = Question::password(gate.prompt);
+            self.task = Some(Task {
+                index,
+                state: TaskState::Login(question, generated_index),
+            });
+            return Status::Ask(question);
+        }
+        if let Some(cb_fn) = incoming_menu.entry {
+            cb_fn(&incoming_menu, interface, context);
+        }
+        match generated_index {
+            Some(generated_index) => self.menu_mgr.push_generated_menu(index, generated_index),
+            None => self.menu_mgr.push_menu(index),
+        }
+        self.audit_command(context, item.command, &[], audit::Stage::Entered);
+        Status::Success
+    }
+
+    /// Check the password for a gated menu, and enter the menu if it is
+    /// right.
+    fn login(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        index: usize,
+        generated_index: Option<usize>,
+        password: &str,
+    ) -> Status {
+        let Some(item) = self.menu_mgr.get_menu(None).item(index) else {
+            return Status::Failure;
+        };
+        let gate = match item.item_type {
+            ItemType::Menu(menu) => menu.gate,
+            ItemType::Generated { generator, .. } => generator(generated_index.unwrap_or(0)).gate,
+            _ => None,
+        };
+        let Some(gate) = gate else {
+            return Status::Failure;
+        };
+        let now_ms = self.now_ms();
+        if self.session.is_locked(gate, now_ms) {
+            self.print_error(
+                interface,
+                format_args!("Error: Too many wrong passwords. Try again later."),
+            );
+            self.audit_command(
+                context,
+                item.command,
+                &[],
+                audit::Stage::Rejected("locked out"),
+            );
+            return Status::Failure;
+        }
+        if !(gate.verify)(context, password) {
+            self.session.failed(gate, now_ms);
+            self.print_error(interface, format_args!("Error: Wrong password"));
+            self.audit_command(
+                context,
+                item.command,
+                &[],
+                audit::Stage::Rejected("wrong password"),
+            );
+            return Status::Failure;
+        }
+        self.session.succeeded(gate);
+        self.enter_menu(interface, context, index, generated_index, true)
+    }
+
+    /// Leave every sub-menu, calling their exit functions, and log out.
+    fn logout(&mut self, interface: &mut I, context: &mut T) {
+        // Recorded first, so the record says who logged out from where
+        self.audit_command(context, "logout", &[], audit::Stage::After(Status::Success));
+        while self.menu_mgr.depth() != 0 {
+            self.exit_menu(interface, context);
+        }
+        self.session.log_out();
+        self.session.level = 0;
+    }
+
+    /// Remember that the operator did something, for the idle timeout.
+    fn touch(&mut self) {
+        if self.session.idle_timeout_ms.is_some() {
+            self.session.last_active_ms = self.now_ms();
+        }
+    }
+
+    /// Log out if the operator has been idle for too long. Returns `true` if
+    /// we did.
+    fn timed_out(&mut self, interface: &mut I, context: &mut T) -> bool {
+        let Some(timeout_ms) = self.session.idle_timeout_ms else {
+            return false;
+        };
+        let Some(now_ms) = self.now_ms() else {
+            return false;
+        };
+        let last_active_ms = *self.session.last_active_ms.get_or_insert(now_ms);
+        if now_ms.wrapping_sub(last_active_ms) < timeout_ms {
+            return false;
+        }
+        self.session.last_active_ms = Some(now_ms);
+        // A running command isn't idle, and there's nothing to lose at the
+        // root menu at level 0
+        if self.is_running() || (self.menu_mgr.depth() == 0 && !self.session.is_elevated()) {
+            return false;
+        }
+        self.cancel(interface, context);
+        self.audit_id = self.audit_id.wrapping_add(1);
+        self.logout(interface, context);
+        writeln!(interface, "\nSession timed out").unwrap();
+        true
+    }
+
+    /// Leave the current sub-menu, calling its exit function.
+    fn exit_menu(&mut self, interface: &mut I, context: &mut T) {
+        let menu = self.menu_mgr.get_menu(None);
+        if let Some(cb_fn) = menu.exit {
+            cb_fn(&menu, interface, context);
+        }
+        self.menu_mgr.pop_menu();
+    }
+
+    /// Handle the `enable` command.
+    fn run_enable_builtin(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        level: Option<&str>,
+    ) -> Status {
+        let Some(level) = level else {
+            self.print_error(
+                interface,
+                format_args!("Error: Insufficient arguments given"),
+            );
+            self.audit_command(
+                context,
+                "enable",
+                &[],
+                audit::Stage::Rejected("insufficient arguments"),
+            );
+            return Status::Failure;
+        };
+        let Ok(level) = level.parse::<u8>() else {
+            self.print_error(
+                interface,
+                format_args!("Error: {:?} is not a privilege level", level),
+            );
+            self.audit_command(
+                context,
+                "enable",
+                &[level],
+                audit::Stage::Rejected("not a privilege level"),
+            );
+            return Status::Failure;
+        };
+        if level <= self.session.level {
+            // Going down doesn't need a password
+            self.set_level(interface, context, level);
+            self.audit_enable(context, level, audit::Stage::After(Status::Success));
+            return Status::Success;
+        }
+        let question = Question::password("Password: ");
+        self.task = Some(Task {
+            index: 0,
+            state: TaskState::Enable(question, level),
+        });
+        Status::Ask(question)
+    }
+
+    /// Check the password for a privilege level, and change to that level
+    /// if it is right.
+    fn check_enable(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        level: u8,
+        password: &str,
+    ) -> Status {
+        let Some(enable) = self.enable else {
+            return Status::Failure;
+        };
+        if !enable(context, level, password) {
+            self.print_error(interface, format_args!("Error: Wrong password"));
+            self.audit_enable(context, level, audit::Stage::Rejected("wrong password"));
+            return Status::Failure;
+        }
+        self.set_level(interface, context, level);
+        self.audit_enable(context, level, audit::Stage::After(Status::Success));
+        Status::Success
+    }
+
+    /// Record an `enable` command, with the level asked for.
+    fn audit_enable(&self, context: &mut T, level: u8, stage: audit::Stage) {
+        let mut digits = [0u8; 3];
+        let mut start = digits.len();
+        let mut rest = level;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + rest % 10;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        // We only wrote ASCII digits
+        let level = core::str::from_utf8(&digits[start..]).unwrap();
+        self.audit_command(context, "enable", &[level], stage);
+    }
+
+    /// Change the privilege level, leaving any menus the operator can no
+    /// longer use.
+    fn set_level(&mut self, interface: &mut I, context: &mut T, level: u8) {
+        self.session.level = level;
+        while self.menu_mgr.depth() != 0 && self.menu_mgr.get_menu(None).privilege > level {
+            self.exit_menu(interface, context);
+        }
+    }
+
+    /// What time is it, if we have a clock?
+    fn now_ms(&mut self) -> Option<u32> {
+        self.clock.as_mut().map(|clock| clock.now_ms())
+    }
+
+    /// If a command asked a question, remember it so the answer can be given
+    /// to the command.
+    fn suspend(
+        &mut self,
+        interface: &mut I,
+        index: usize,
+        item: &Item<I, T>,
+        status: Status,
+    ) -> Status {
+        let state = match status {
+            Status::Ask(question) => TaskState::Asking(question),
+            Status::Pending => TaskState::Running,
+            Status::Success | Status::Failure => return status,
+        };
+        if !matches!(item.item_type, ItemType::Resumable { .. }) {
+            self.print_error(
+                interface,
+                format_args!("Error: {:?} is not resumable", item.command),
+            );
+            return Status::Failure;
+        }
+        self.task = Some(Task { index, state });
+        status
+    }
+
+    /// Give the operator's answer to the command which asked for it.
+    fn answer(&mut self, interface: &mut I, context: &mut T, line: &str) -> Status {
+        // Go to the next line, below the question
+        writeln!(interface).unwrap();
+        let Some(question) = self.question() else {
+            return Status::Failure;
+        };
+        match self.task {
+            Some(Task {
+                index,
+                state: TaskState::Login(_, generated_index),
+            }) => {
+                self.task = None;
+                return self.login(interface, context, index, generated_index, line);
+            }
+            Some(Task {
+                state: TaskState::Enable(_, level),
+                ..
+            }) => {
+                self.task = None;
+                return self.check_enable(interface, context, level, line);
+            }
+            Some(Task {
+                index,
+                state: TaskState::Confirm(len),
+            }) => {
+                self.task = None;
+                if !is_yes(line) {
+                    if let Some(item) = self.menu_mgr.get_menu(None).item(index) {
+                        self.audit_command(
+                            context,
+                            item.command,
+                            &[],
+                            audit::Stage::Rejected("not confirmed"),
+                        );
+                    }
+                    return Status::Failure;
+                }
+                return self.run_confirmed(interface, context, index, len);
+            }
+            _ => {}
+        }
+        let event = match question.kind {
+            AnswerKind::YesNo => Event::Confirmed(is_yes(line)),
+            AnswerKind::Line | AnswerKind::Password => Event::Answer(line),
+        };
+        self.resume(interface, context, event)
+    }
+
+    /// Let a command which is still running do some more work.
+    fn poll(&mut self, interface: &mut I, context: &mut T) -> Status {
+        if self.take_interrupt() {
+            writeln!(interface, "^C").unwrap();
+            self.cancel(interface, context);
+            return Status::Failure;
+        }
+        if self.is_watching() {
+            return self.poll_watch(interface, context);
+        }
+        self.resume(interface, context, Event::Poll)
+    }
+
+    /// Give up on any command which hasn't finished, telling it so if it is
+    /// waiting for an answer or still running.
+    fn cancel(&mut self, interface: &mut I, context: &mut T) {
+        if let Some(Task {
+            state: TaskState::Asking(_) | TaskState::Running,
+            ..
+        }) = self.task
+        {
+            self.resume(interface, context, Event::Cancelled);
+        }
+        self.task = None;
+    }
+
+    /// Carry on with the command which has not finished yet.
+    fn resume(&mut self, interface: &mut I, context: &mut T, event: Event) -> Status {
+        let Some(task) = self.task.take() else {
+            return Status::Failure;
+        };
+        let menu = self.menu_mgr.get_menu(None);
+        // The item may have been removed from an `ItemTable` while we waited
+        let Some(item) = menu.item(task.index) else {
+            return Status::Failure;
+        };
+        let ItemType::Resumable {
+            resume, parameters, ..
+        } = item.item_type
+        else {
+            return Status::Failure;
+        };
+        let status = resume(&menu, item, event, interface, context);
+        let status = self.suspend(interface, task.index, item, status);
+        if let Status::Success | Status::Failure = status {
+            self.audit(
+                context,
+                item,
+                parameters,
+                &[],
+                audit::Stage::Finished(status),
+            );
+        }
+        self.last_status = status;
+        status
+    }
+
+    /// Remember a command line, and ask the operator whether to run it.
+    fn ask_to_confirm(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        index: usize,
+        command_line: &str,
+    ) -> Status {
+        let Some(saved) = self.saved_line.get_mut(0..command_line.len()) else {
+            self.print_error(
+                interface,
+                format_args!("Error: Command too long to confirm. Try --yes."),
+            );
+            if let Some(item) = self.menu_mgr.get_menu(None).item(index) {
+                self.audit_command(
+                    context,
+                    item.command,
+                    &[],
+                    audit::Stage::Rejected("too long to confirm"),
+                );
+            }
+            return Status::Failure;
+        };
+        saved.copy_from_slice(command_line.as_bytes());
+        self.task = Some(Task {
+            index,
+            state: TaskState::Confirm(command_line.len()),
+        });
+        Status::Ask(CONFIRM)
+    }
+
+    /// Run the command line the operator said yes to.
+    fn run_confirmed(
+        &mut self,
+        interface: &mut I,
+        context: &mut T,
+        index: usize,
+        len: usize,
+    ) -> Status {
+        let menu = self.menu_mgr.get_menu(None);
+        // The item may have been removed from an `ItemTable` while we waited
+        let Some(item) = menu.item(index) else {
+            return Status::Failure;
+        };
+        // We only ever save whole strings
+        let command_line = core::str::from_utf8(&self.saved_line[0..len]).unwrap();
+        let status = self.call_function(interface, context, &menu, item, command_line);
+        let status = self.suspend(interface, index, item, status);
+        self.last_status = status;
+        status
+    }
+
+    /// The question the operator is being asked, if there is one.
+    fn question(&self) -> Option<Question> {
+        match self.task {
+            Some(Task {
+                state:
+                    TaskState::Asking(question)
+                    | TaskState::Login(question, _)
+                    | TaskState::Enable(question, _),
+                ..
+            }) => Some(question),
+            Some(Task {
+                state: TaskState::Confirm(_),
+                ..
+            }) => Some(CONFIRM),
+            _ => None,
+        }
+    }
+
+    /// What to show instead of the prompt while the operator is asked a
+    /// question.
+    fn question_prompt(&self) -> Option<[&'a str; 3]> {
+        if let Some(Task {
+            index,
+            state: TaskState::Confirm(_),
+        }) = self.task
+        {
+            let menu = self.menu_mgr.get_menu(None);
+            let command = menu.item(index).map_or("", |item| item.command);
+            return Some(["Really run '", command, "'? [y/N] "]);
+        }
+        self.question().map(|question| [question.prompt, "", ""])
+    }
+
+    /// Leave any sub-menus which have been removed from an [`ItemTable`],
+    /// giving up on any command which was waiting in one. Returns `true` if
+    /// we had to leave any.
+    fn pop_removed_menus(&mut self) -> bool {
+        if !self.menu_mgr.pop_removed_menus() {
+            return false;
+        }
+        // Its item index was for the menu which has gone
+        self.task = None;
+        true
+    }
+
+    /// Is there a command which is still running (or being watched)?
+    fn is_running(&self) -> bool {
+        matches!(
+            self.task,
+            Some(Task {
+                state: TaskState::Running | TaskState::Watch { .. },
+                ..
+            })
+        )
+    }
+
+    /// Is `watch` running a command over and over?
+    fn is_watching(&self) -> bool {
+        matches!(
+            self.task,
+            Some(Task {
+                state: TaskState::Watch { .. },
+                ..
+            })
+        )
+    }
+
+    /// Is the operator typing an answer which should not be shown?
+    fn masked(&self) -> bool {
+        self.question()
+            .is_some_and(|question| question.kind == AnswerKind::Password)
+    }
+
+    fn print_short_help(
+        &mut self,
+        interface: &mut I,
+        item: &Item<I, T>,
+        enabled: Result<(), &'static str>,
+    ) {
+        let mut has_options = false;
+        match item.item_type {
+            ItemType::Callback { parameters, .. } | ItemType::Resumable { parameters, .. } => {
+                write!(interface, "  {}", item.command).unwrap();
+                if !parameters.is_empty() {
+                    for param in parameters.iter().map(Parameter::plain) {
+                        match param {
+                            Parameter::Mandatory { parameter_name, .. } => {
+                                write!(interface, " <{}>", parameter_name).unwrap();
+                            }
+                            Parameter::Optional { parameter_name, .. } => {
+                                write!(interface, " [ <{}> ]", parameter_name).unwrap();
+                            }
+                            Parameter::Named { .. } => {
+                                has_options = true;
+                            }
+                            Parameter::NamedValue { .. } => {
+                                has_options = true;
+                            }
+                            Parameter::Secret(_) => unreachable!(),
+                        }
+                    }
+                }
+            }
+            ItemType::Menu(_menu) => {
+                write!(interface, "  {}", item.command).unwrap();
+            }
+            ItemType::Generated { count, .. } => {
+                write!(
+                    interface,
+                    "  {0}0..{0}{1}",
+                    item.command,
+                    count.saturating_sub(1)
+                )
+                .unwrap();
+            }
+            ItemType::_Dummy => {
+                write!(interface, "  {}", item.command).unwrap();
+            }
+        }
+        if has_options {
+            write!(interface, " [OPTIONS...]").unwrap();
+        }
+        if let Some((first, rest)) = item.aliases.split_first() {
+            write!(interface, " (aliases: {}", first).unwrap();
+            for alias in rest {
+                write!(interface, ", {}", alias).unwrap();
+            }
+            write!(interface, ")").unwrap();
+        }
+        if let Err(reason) = enabled {
+            write!(interface, " (not available: {})", reason).unwrap();
+        }
+        writeln!(interface).unwrap();
+    }
+
+    fn print_long_help(&mut self, interface: &mut I, item: &Item<I, T>) {
+        self.print_heading(interface, "SUMMARY:");
+        match item.item_type {
+            ItemType::Callback { parameters, .. } | ItemType::Resumable { parameters, .. } => {
+                write!(interface, "  {}", item.command).unwrap();
+                if !parameters.is_empty() {
+                    for param in parameters.iter().map(Parameter::plain) {
+                        match param {
+                            Parameter::Mandatory { parameter_name, .. } => {
+                                write!(interface, " <{}>", parameter_name).unwrap();
+                            }
+                            Parameter::Optional { parameter_name, .. } => {
+                                write!(interface, " [ <{}> ]", parameter_name).unwrap();
+                            }
+                            Parameter::Named { parameter_name, .. } => {
+                                write!(interface, " [ --{} ]", parameter_name).unwrap();
+                            }
+                            Parameter::NamedValue {
+                                parameter_name,
+                                argument_name,
+                                ..
+                            } => {
+                                write!(interface, " [ --{}={} ]", parameter_name, argument_name)
+                                    .unwrap();
+                            }
+                            Parameter::Secret(_) => unreachable!(),
+                        }
+                    }
+                    writeln!(interface, "\n").unwrap();
+                    self.print_heading(interface, "PARAMETERS:");
+                    let default_help = "Undocumented option";
+                    for param in parameters.iter().map(Parameter::plain) {
+                        match param {
+                            Parameter::Mandatory {
+                                parameter_name,
+                                help,
+                                ..
+                            } => {
+                                writeln!(interface, "  <{}>", parameter_name).unwrap();
+                                self.print_help_text(interface, help.unwrap_or(default_help), 4);
+                                writeln!(interface).unwrap();
+                            }
+                            Parameter::Optional {
+                                parameter_name,
+                                help,
+                                ..
+                            } => {
+                                writeln!(interface, "  <{}>", parameter_name).unwrap();
+                                self.print_help_text(interface, help.unwrap_or(default_help), 4);
+                                writeln!(interface).unwrap();
+                            }
+                            Parameter::Named {
+                                parameter_name,
+                                help,
+                            } => {
+                                writeln!(interface, "  --{}", parameter_name).unwrap();
+                                self.print_help_text(interface, help.unwrap_or(default_help), 4);
+                                writeln!(interface).unwrap();
+                            }
+                            Parameter::NamedValue {
+                                parameter_name,
+                                argument_name,
+                                help,
+                                ..
+                            } => {
+                                writeln!(interface, "  --{}={}", parameter_name, argument_name)
+                                    .unwrap();
+                                self.print_help_text(interface, help.unwrap_or(default_help), 4);
+                                writeln!(interface).unwrap();
+                            }
+                            Parameter::Secret(_) => unreachable!(),
+                        }
+                    }
+                }
+            }
+            ItemType::Menu(_menu) => {
+                write!(interface, "  {}", item.command).unwrap();
+            }
+            ItemType::Generated { count, .. } => {
+                write!(
+                    interface,
+                    "  {0}0..{0}{1}",
+                    item.command,
+                    count.saturating_sub(1)
+                )
+                .unwrap();
+            }
+            ItemType::_Dummy => {
+                write!(interface, "  {}", item.command).unwrap();
+            }
+        }
+        if let Some(help) = item.help {
+            writeln!(interface, "\n").unwrap();
+            self.print_heading(interface, "DESCRIPTION:");
+            self.print_help_text(interface, help, 0);
+        }
+    }
+
+    /// Print some help text indented by `indent` spaces, wrapped if a
+    /// width has been set.
+    fn print_help_text(&self, interface: &mut I, text: &str, indent: usize) {
+        match self.help_width {
+            Some(width) => write_wrapped(interface, text, indent, width),
+            None => writeln!(interface, "{:indent$}{}", "", text).unwrap(),
+        }
+    }
+
+    fn call_function(
+        &self,
+        interface: &mut I,
+        context: &mut T,
+        parent_menu: &Menu<I, T>,
+        item: &Item<I, T>,
+        command: &str,
+    ) -> Status {
+        let (ItemType::Callback {
+            function: callback_function,
+            parameters,
+        }
+        | ItemType::Resumable {
+            function: callback_function,
+            parameters,
+            ..
+        }) = item.item_type
+        else {
+            unreachable!();
+        };
+        let mandatory_parameter_count = parameters
+            .iter()
+            .filter(|p| matches!(p.plain(), Parameter::Mandatory { .. }))
+            .count();
+        let positional_parameter_count = parameters
+            .iter()
+            .filter(|p| {
+                matches!(
+                    p.plain(),
+                    Parameter::Mandatory { .. } | Parameter::Optional { .. }
+                )
+            })
+            .count();
+        // Skip the command (or alias) the user typed - the rest are arguments
+        let arguments = command
+            .split_whitespace()
+            .skip(1)
+            .filter(|arg| !(item.confirm && is_yes_flag(arg)));
+        // Room for `--named` arguments typed in a different case, re-written
+        // with the name as it was declared
+        let mut renamed = [0u8; MAX_RENAMED_LEN];
+        let mut spare: &mut [u8] = &mut renamed;
+        let mut argument_buffer: [&str; 16] = [""; 16];
+        let mut argument_count = 0;
+        let mut positional_arguments = 0;
+        for (slot, arg) in argument_buffer.iter_mut().zip(arguments) {
+            *slot = arg;
+            argument_count += 1;
+            if let Some(tail) = arg.strip_prefix("--") {
+                // Validate named argument
+                let mut found = None;
+                for param in parameters.iter().map(Parameter::plain) {
+                    match param {
+                        Parameter::Named { parameter_name, .. }
+                            if self.matches(tail, parameter_name) =>
+                        {
+                            found = Some(*parameter_name);
+                            break;
+                        }
+                        Parameter::NamedValue { parameter_name, .. }
+                            if tail
+                                .split_once('=')
+                                .is_some_and(|(name, _)| self.matches(name, parameter_name)) =>
+                        {
+                            found = Some(*parameter_name);
+                            break;
+                        }
+                        _ => {
+                            // Ignore
+                        }
+                    }
+                }
+                if let Some(parameter_name) = found {
+                    if !tail.starts_with(parameter_name) {
+                        // Only the case is different, so it is the same length
+                        if spare.len() < arg.len() {
+                            self.print_error(interface, format_args!("Error: Arguments too long"));
+                            let args = &argument_buffer[0..argument_count];
+                            let stage = audit::Stage::Rejected("arguments too long");
+                            self.audit(context, item, parameters, args, stage);
+                            return Status::Failure;
+                        }
+                        let (this, rest) = core::mem::take(&mut spare).split_at_mut(arg.len());
+                        spare = rest;
+                        this.copy_from_slice(arg.as_bytes());
+                        this[2..2 + parameter_name.len()]
+                            .copy_from_slice(parameter_name.as_bytes());
+                        *slot = core::str::from_utf8_mut(this).unwrap();
+                    }
+                } else {
+                    self.print_error(
+                        interface,
+                        format_args!("Error: Did not understand {:?}", arg),
+                    );
+                    let typed = tail.split_once('=').map_or(tail, |(name, _)| name);
+                    let names = parameters.iter().filter_map(|param| match param.plain() {
+                        Parameter::Named { parameter_name, .. }
+                        | Parameter::NamedValue { parameter_name, .. } => Some(*parameter_name),
+                        _ => None,
+                    });
+                    suggest(interface, typed, "--", names, self.ignore_case);
+                    let args = &argument_buffer[0..argument_count];
+                    let stage = audit::Stage::Rejected("unknown option");
+                    self.audit(context, item, parameters, args, stage);
+                    return Status::Failure;
+                }
+            } else {
+                positional_arguments += 1;
+            }
+        }
+        let args = &argument_buffer[0..argument_count];
+        if positional_arguments < mandatory_parameter_count {
+            self.print_error(
+                interface,
+                format_args!("Error: Insufficient arguments given"),
+            );
+            let stage = audit::Stage::Rejected("insufficient arguments");
+            self.audit(context, item, parameters, args, stage);
+            Status::Failure
+        } else if positional_arguments > positional_parameter_count {
+            self.print_error(interface, format_args!("Error: Too many arguments given"));
+            let stage = audit::Stage::Rejected("too many arguments");
+            self.audit(context, item, parameters, args, stage);
+            Status::Failure
+        } else {
+            self.audit(context, item, parameters, args, audit::Stage::Before);
+            let status = callback_function(parent_menu, item, args, interface, context);
+            self.audit(context, item, parameters, args, audit::Stage::After(status));
+            status
+        }
+    }
+
+    /// Tell the audit hook, if there is one, about a command.
+    fn audit(
+        &self,
+        context: &mut T,
+        item: &Item<I, T>,
+        parameters: &[Parameter],
+        args: &[&str],
+        stage: audit::Stage,
+    ) {
+        if self.audit.is_none() {
+            return;
+        }
+        let mut redacted = [""; 16];
+        let mut secrets = SecretArgs::new(parameters, self.ignore_case);
+        for (slot, arg) in redacted.iter_mut().zip(args) {
+            *slot = if secrets.is_secret(arg) {
+                audit::REDACTED
+            } else {
+                arg
+            };
+        }
+        self.audit_command(context, item.command, &redacted[0..args.len()], stage);
+    }
+
+    /// Tell the audit function (if there is one) about a command. Any
+    /// secrets must already have been taken out of `args`.
+    fn audit_command(&self, context: &mut T, command: &str, args: &[&str], stage: audit::Stage) {
+        let Some(audit_fn) = self.audit else {
+            return;
+        };
+        let (path, depth) = self.menu_path();
+        audit_fn(
+            context,
+            &audit::Record {
+                id: self.audit_id,
+                path: &path[0..depth],
+                command,
+                args,
+                privilege: self.session.level,
+                stage,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_io::Write;
+
+    fn dummy(
+        _menu: &Menu<(), u32>,
+        _item: &Item<(), u32>,
+        _args: &[&str],
+        _interface: &mut (),
+        _context: &mut u32,
+    ) -> Status {
+        Status::Success
+    }
+
+    /// Collects everything the runner prints, so we can check it
+    struct Output {
+        buffer: [u8; 2048],
+        used: usize,
+        /// How many more times `read_ready` is called before a key is pressed
+        polls_until_key: usize,
+        /// The key which is pressed
+        key: u8,
+    }
+
+    impl Output {
+        fn new() -> Self {
+            Self {
+                buffer: [0; 2048],
+                used: 0,
+                polls_until_key: usize::MAX,
+                key: b' ',
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buffer[0..self.used]).unwrap()
+        }
+
+        fn clear(&mut self) {
+            self.used = 0;
+        }
+    }
+
+    impl embedded_io::ErrorType for Output {
+        type Error = core::convert::Infallible;
+    }
+
+    impl embedded_io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.buffer.len() - self.used);
+            self.buffer[self.used..self.used + len].copy_from_slice(&buf[0..len]);
+            self.used += len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl embedded_io::Read for Output {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            buf[0] = self.key;
+            Ok(1)
+        }
+    }
+
+    impl embedded_io::ReadReady for Output {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            if self.polls_until_key == 0 {
+                self.polls_until_key = usize::MAX;
+                return Ok(false);
+            }
+            self.polls_until_key -= 1;
+            Ok(self.polls_until_key == 0)
+        }
+    }
+
+    /// A clock which moves on 100ms every time you look at it
+    struct Ticker(u32);
+
+    impl watch::TimeSource for Ticker {
+        fn now_ms(&mut self) -> u32 {
+            self.0 = self.0.wrapping_add(100);
+            self.0
+        }
+    }
+
+    fn count(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        _args: &[&str],
+        _interface: &mut Output,
+        context: &mut u32,
+    ) -> Status {
+        *context += 1;
+        Status::Success
+    }
+
+    const TEST_MENU: Menu<Output, u32> = Menu::new(
+        "root",
+        &[
+            &Item::new(
+                "reboot",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[],
+                },
+            )
+            .help("Reboot the unit")
+            .aliases(&["reset", "rst"]),
+            &Item::new(
+                "led",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[
+                        Parameter::Named {
+                            parameter_name: "verbose",
+                            help: None,
+                        },
+                        Parameter::NamedValue {
+                            parameter_name: "level",
+                            argument_name: "INT",
+                            help: None,
+                        },
+                    ],
+                },
+            ),
+            &Item::new(
+                "factory",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[],
+                },
+            )
+            .visibility(Visibility::Hidden),
+            &Item::new(
+                "erase",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[],
+                },
+            )
+            .enabled(|context| {
+                if *context >= 10 {
+                    Ok(())
+                } else {
+                    Err("bootloader is locked")
+                }
+            }),
+            &Item::new(
+                "ch",
+                ItemType::Generated {
+                    generator: channel_menu,
+                    count: 12,
+                },
+            )
+            .help("Channel settings"),
+            &Item::new(
+                "fail",
+                ItemType::Callback {
+                    function: fail,
+                    parameters: &[],
+                },
+            ),
+            &Item::new(
+                "wipe",
+                ItemType::Resumable {
+                    function: wipe,
+                    parameters: &[],
+                    resume: wipe_resume,
+                },
+            ),
+            &Item::new(
+                "flash",
+                ItemType::Resumable {
+                    function: flash,
+                    parameters: &[],
+                    resume: flash_resume,
+                },
+            ),
+            &Item::new(
+                "scan",
+                ItemType::Callback {
+                    function: scan,
+                    parameters: &[],
+                },
+            ),
+            &Item::new(
+                "debug",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[],
+                },
+            )
+            .visibility(Visibility::When(|context| *context >= 100)),
+        ],
+    );
+
+    fn fail(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        _args: &[&str],
+        interface: &mut Output,
+        _context: &mut u32,
+    ) -> Status {
+        writeln!(interface, "Failed!").unwrap();
+        Status::Failure
+    }
+
+    /// Asks for confirmation, then a password
+    fn wipe(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        _args: &[&str],
+        _interface: &mut Output,
+        _context: &mut u32,
+    ) -> Status {
+        Status::Ask(Question::yes_no("Really wipe? [y/N] "))
+    }
+
+    fn wipe_resume(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        event: Event,
+        interface: &mut Output,
+        context: &mut u32,
+    ) -> Status {
+        match event {
+            Event::Confirmed(true) => Status::Ask(Question::password("Password: ")),
+            Event::Answer("hunter2") => {
+                *context += 100;
+                Status::Success
+            }
+            _ => {
+                writeln!(interface, "Not wiped").unwrap();
+                Status::Failure
+            }
+        }
+    }
+
+    /// Keeps running until it has been polled three times
+    fn flash(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        _args: &[&str],
+        _interface: &mut Output,
+        _context: &mut u32,
+    ) -> Status {
+        Status::Pending
+    }
+
+    fn flash_resume(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        event: Event,
+        interface: &mut Output,
+        context: &mut u32,
+    ) -> Status {
+        if event == Event::Cancelled {
+            writeln!(interface, "Flash abandoned").unwrap();
+            return Status::Failure;
+        }
+        assert_eq!(event, Event::Poll);
+        *context += 1;
+        if !(*context).is_multiple_of(3) {
+            return Status::Pending;
+        }
+        writeln!(interface, "Flashed").unwrap();
+        Status::Success
+    }
+
+    static INTERRUPT: interrupt::Interrupt = interrupt::Interrupt::new();
+
+    /// Counts until it is interrupted
+    fn scan(
+        _menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        _args: &[&str],
+        interface: &mut Output,
+        context: &mut u32,
+    ) -> Status {
+        while !INTERRUPT.poll(interface) {
+            *context += 1;
+        }
+        Status::Success
+    }
+
+    fn read_index(
+        menu: &Menu<Output, u32>,
+        _item: &Item<Output, u32>,
+        _args: &[&str],
+        _interface: &mut Output,
+        context: &mut u32,
+    ) -> Status {
+        *context = menu.index().unwrap() as u32;
+        Status::Success
+    }
+
+    fn channel_menu(_index: usize) -> Menu<'static, Output, u32> {
+        const CHANNEL_MENU: Menu<Output, u32> = Menu::new(
+            "ch",
+            &[&Item::new(
+                "read",
+                ItemType::Callback {
+                    function: read_index,
+                    parameters: &[],
+                },
+            )],
+        );
+        CHANNEL_MENU
+    }
+
+    fn type_line<B: AsMut<[u8]> + ?Sized>(
+        r: &mut Runner<Output, u32, B>,
+        line: &str,
+        context: &mut u32,
+    ) {
+        r.interface.clear();
+        for b in line.bytes() {
+            r.input_byte(b, context);
+        }
+        r.input_byte(b'\r', context);
+    }
+
+    #[test]
+    fn aliases() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "reboot", &mut context);
+        type_line(&mut r, "reset", &mut context);
+        type_line(&mut r, "rst", &mut context);
+        assert_eq!(context, 3);
+        type_line(&mut r, "help", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("  reboot (aliases: reset, rst)\n"));
+        type_line(&mut r, "help rst", &mut context);
+        assert!(r.interface.as_str().contains("Reboot the unit"));
+    }
+
+    #[test]
+    fn complete_alias() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "rs\t", &mut context);
+        assert_eq!(context, 1);
+    }
+
+    #[cfg(not(feature = "echo"))]
+    #[test]
+    fn complete_without_echo() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        r.interface.clear();
+        for b in b"rs\t" {
+            r.input_byte(*b, &mut context);
+        }
+        // Nothing is shown until the whole line is echoed
+        assert_eq!(r.interface.as_str(), "");
+        r.input_byte(b'\r', &mut context);
+        assert!(r.interface.as_str().starts_with("\rrst "));
+        assert_eq!(context, 1);
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("reboot", "reboot", false), Some(0));
+        assert_eq!(edit_distance("rebot", "reboot", false), Some(1));
+        assert_eq!(edit_distance("rbeoot", "reboot", false), Some(2));
+        assert_eq!(edit_distance("", "help", false), Some(4));
+        assert_eq!(edit_distance("HELP", "help", false), Some(4));
+        assert_eq!(edit_distance("HELP", "help", true), Some(0));
+        assert_eq!(
+            edit_distance(
+                "x",
+                "abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyz",
+                false
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "rebot", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Command \"rebot\" not found. Try 'help'.\nDid you mean \"reboot\"?\n"));
+        type_line(&mut r, "lde", &mut context);
+        assert!(r.interface.as_str().contains("Did you mean \"led\"?\n"));
+        type_line(&mut r, "xyzzy", &mut context);
+        assert!(!r.interface.as_str().contains("Did you mean"));
+        type_line(&mut r, "led --verbos --levl=3", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Did not understand \"--verbos\"\nDid you mean \"--verbose\"?\n"));
+        type_line(&mut r, "led --levl=3", &mut context);
+        assert!(r.interface.as_str().contains("Did you mean \"--level\"?\n"));
+        assert_eq!(context, 0);
+    }
+
+    #[test]
+    fn hidden_items() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "help", &mut context);
+        assert!(!r.interface.as_str().contains("factory"));
+        assert!(!r.interface.as_str().contains("debug"));
+        type_line(&mut r, "help factory", &mut context);
+        assert!(r.interface.as_str().contains("I can't help with"));
+        type_line(&mut r, "factry", &mut context);
+        assert!(!r.interface.as_str().contains("Did you mean"));
+        // Hidden items still run by default
+        type_line(&mut r, "factory", &mut context);
+        type_line(&mut r, "debug", &mut context);
+        assert_eq!(context, 2);
+        // Tab completion does not know about them
+        type_line(&mut r, "fac\t", &mut context);
+        assert_eq!(context, 2);
+        // Unless we say otherwise
+        r.set_dispatch_hidden(false);
+        type_line(&mut r, "factory", &mut context);
+        assert!(r.interface.as_str().contains("not found"));
+        assert_eq!(context, 2);
+        // Items can appear depending on the context
+        context = 100;
+        type_line(&mut r, "help", &mut context);
+        assert!(r.interface.as_str().contains("  debug\n"));
+        type_line(&mut r, "debug", &mut context);
+        assert_eq!(context, 101);
+    }
+
+    #[test]
+    fn disabled_items() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "help", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("  erase (not available: bootloader is locked)\n"));
+        type_line(&mut r, "erase", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: not available: bootloader is locked\n"));
+        assert_eq!(context, 0);
+        context = 10;
+        type_line(&mut r, "help", &mut context);
+        assert!(r.interface.as_str().contains("  erase\n"));
+        type_line(&mut r, "erase", &mut context);
+        assert_eq!(context, 11);
+    }
+
+    const SENSOR_MENU: Item<Output, u32> = Item::new(
+        "sensor",
+        ItemType::Menu(&Menu::new(
+            "sensor",
+            &[&Item::new(
+                "read",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[],
+                },
+            )],
+        )),
+    )
+    .help("Optional sensor");
+
+    #[test]
+    fn dynamic_items() {
+        let table: ItemTable<Output, u32, 1> = ItemTable::new();
+        let menu = TEST_MENU.dynamic_items(&table);
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(menu, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "sensor", &mut context);
+        assert!(r.interface.as_str().contains("not found"));
+        assert_eq!(table.register(&SENSOR_MENU), Ok(()));
+        assert_eq!(table.register(&SENSOR_MENU), Err(Error::Duplicate));
+        const OTHER: Item<Output, u32> = Item::new(
+            "other",
+            ItemType::Callback {
+                function: count,
+                parameters: &[],
+            },
+        );
+        assert_eq!(table.register(&OTHER), Err(Error::TableFull));
+        type_line(&mut r, "help", &mut context);
+        assert!(r.interface.as_str().contains("  sensor\n"));
+        type_line(&mut r, "sensor", &mut context);
+        type_line(&mut r, "read", &mut context);
+        type_line(&mut r, "exit", &mut context);
+        assert_eq!(context, 1);
+        assert!(table.remove("sensor").is_some());
+        type_line(&mut r, "sensor", &mut context);
+        assert!(r.interface.as_str().contains("not found"));
+    }
+
+    #[test]
+    fn removed_while_inside() {
+        let table: ItemTable<Output, u32, 1> = ItemTable::new();
+        let menu = TEST_MENU.dynamic_items(&table);
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(menu, &mut buffer, Output::new(), &mut context);
+        table.register(&SENSOR_MENU).unwrap();
+        type_line(&mut r, "sensor", &mut context);
+        assert!(r.interface.as_str().ends_with("\nsensor> "));
+
+        // The next key press finds we're back at the top
+        table.remove("sensor");
+        r.interface.clear();
+        r.input_byte(b'r', &mut context);
+        assert_eq!(r.interface.as_str(), "\r> r");
+        r.input_byte(0x08, &mut context);
+        type_line(&mut r, "read", &mut context);
+        assert!(r.interface.as_str().contains("Command \"read\" not found"));
+        assert_eq!(context, 0);
+
+        // So does a replacement in the same slot
+        table.register(&SENSOR_MENU).unwrap();
+        type_line(&mut r, "sensor", &mut context);
+        table.remove("sensor");
+        const SENSOR: Item<Output, u32> = Item::new(
+            "sensor",
+            ItemType::Callback {
+                function: count,
+                parameters: &[],
+            },
+        );
+        table.register(&SENSOR).unwrap();
+        type_line(&mut r, "sensor", &mut context);
+        assert!(r.interface.as_str().ends_with("\n> "));
+        assert_eq!(context, 1);
+    }
+
+    #[test]
+    fn generated_menus() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "help", &mut context);
+        assert!(r.interface.as_str().contains("  ch0..ch11\n"));
+        type_line(&mut r, "ch10", &mut context);
+        assert!(r.interface.as_str().ends_with("\nch10> "));
+        type_line(&mut r, "read", &mut context);
+        assert_eq!(context, 10);
+        type_line(&mut r, "exit", &mut context);
+        type_line(&mut r, "ch2", &mut context);
+        type_line(&mut r, "read", &mut context);
+        assert_eq!(context, 2);
+        type_line(&mut r, "exit", &mut context);
+        type_line(&mut r, "ch12", &mut context);
+        assert!(r.interface.as_str().contains("not found"));
+        type_line(&mut r, "ch", &mut context);
+        assert!(r.interface.as_str().contains("not found"));
+        type_line(&mut r, "ch010", &mut context);
+        assert!(r.interface.as_str().contains("not found"));
+        type_line(&mut r, "ch0", &mut context);
+        assert!(r.interface.as_str().ends_with("\nch0> "));
+        type_line(&mut r, "exit", &mut context);
+
+        // Help for the family doesn't need an index
+        type_line(&mut r, "help ch", &mut context);
+        assert!(r.interface.as_str().contains("  ch0..ch11\n"));
+        assert!(r.interface.as_str().contains("Channel settings"));
+        type_line(&mut r, "help ch7", &mut context);
+        assert!(r.interface.as_str().contains("  ch0..ch11\n"));
+    }
+
+    #[test]
+    fn multiple_commands() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        type_line(&mut r, "reboot; reboot;reboot", &mut context);
+        assert_eq!(context, 3);
+        type_line(&mut r, "reboot && reboot", &mut context);
+        assert_eq!(context, 5);
+        type_line(&mut r, "fail && reboot && reboot; reboot", &mut context);
+        assert_eq!(context, 6);
+        type_line(&mut r, "no-such-command && reboot", &mut context);
+        assert_eq!(context, 6);
+        type_line(&mut r, "led --bad && reboot", &mut context);
+        assert_eq!(context, 6);
+        type_line(&mut r, "fail ; reboot ;", &mut context);
+        assert_eq!(context, 7);
+        type_line(&mut r, "ch3 && read; exit", &mut context);
+        assert_eq!(context, 3);
+        assert!(r.interface.as_str().ends_with("\n> "));
+    }
+
+    const SCRIPT: &str = "# Reboot a few times
+reboot
+
+reset; rst
+fail
+reboot
+";
+
+    #[test]
+    fn scripts() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        r.interface.clear();
+        let options = script::ScriptOptions::default();
+        assert_eq!(
+            r.run_script(SCRIPT, options, &mut context),
+            Err(script::ScriptError::Failed(5))
+        );
+        assert_eq!(context, 3);
+        assert_eq!(r.interface.as_str(), "Failed!\n");
+
+        let options = script::ScriptOptions {
+            echo: true,
+            on_error: script::OnError::Continue,
+        };
+        r.interface.clear();
+        assert_eq!(
+            r.run_script(SCRIPT, options, &mut context),
+            Err(script::ScriptError::Failed(5))
+        );
+        assert_eq!(context, 7);
+        assert_eq!(
+            r.interface.as_str(),
+            "> reboot\n> reset; rst\n> fail\nFailed!\n> reboot\n"
+        );
+    }
+
+    #[test]
+    fn scripts_from_reader() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        let mut line_buffer = [0u8; 16];
+        let options = script::ScriptOptions::default();
+        let mut reader = SCRIPT.as_bytes();
+        assert_eq!(
+            r.run_script_from(&mut reader, &mut line_buffer, options, &mut context),
+            Err(script::ScriptError::Failed(5))
+        );
+        assert_eq!(context, 3);
+
+        // No newline at the end
+        let mut reader = "reboot\nreboot".as_bytes();
+        assert_eq!(
+            r.run_script_from(&mut reader, &mut line_buffer, options, &mut context),
+            Ok(())
+        );
+        assert_eq!(context, 5);
+
+        let mut reader = "reboot\nreboot; reboot; reboot; reboot\n".as_bytes();
+        assert_eq!(
+            r.run_script_from(&mut reader, &mut line_buffer, options, &mut context),
+            Err(script::ScriptError::LineTooLong(2))
+        );
+        assert_eq!(context, 6);
+
+        // A read error wins over a line which failed
+        let options = script::ScriptOptions {
+            on_error: script::OnError::Continue,
+            ..options
+        };
+        let mut reader = BrokenReader("fail\nreboot\nreb".as_bytes());
+        assert_eq!(
+            r.run_script_from(&mut reader, &mut line_buffer, options, &mut context),
+            Err(script::ScriptError::Read(embedded_io::ErrorKind::Other))
+        );
+        assert_eq!(context, 7);
+    }
+
+    /// Gives an error once it runs out
+    struct BrokenReader<'s>(&'s [u8]);
+
+    impl embedded_io::ErrorType for BrokenReader<'_> {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl embedded_io::Read for BrokenReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            match self.0.read(buf) {
+                Ok(0) if !buf.is_empty() => Err(embedded_io::ErrorKind::Other),
+                Ok(n) => Ok(n),
+                Err(_) => Err(embedded_io::ErrorKind::Other),
+            }
+        }
+    }
+
+    #[test]
+    fn operator_aliases() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+
+        // Not available until there is somewhere to keep them
+        type_line(&mut r, "alias", &mut context);
+        assert!(r.interface.as_str().contains("Command \"alias\" not found"));
+
+        let mut alias_buffer = [0u8; 40];
+        r.set_aliases(alias::Aliases::new(&mut alias_buffer));
+        type_line(&mut r, "alias rr \"reboot; reboot\"", &mut context);
+        assert_eq!(context, 0);
+        type_line(&mut r, "rr", &mut context);
+        assert_eq!(context, 2);
+
+        // Failures are passed on, and extra words go on the end
+        type_line(&mut r, "alias rf \"reboot; fail\"", &mut context);
+        type_line(&mut r, "rf && reboot", &mut context);
+        assert_eq!(context, 3);
+        assert!(r.interface.as_str().contains("Failed!"));
+        type_line(&mut r, "alias rf led", &mut context);
+        type_line(&mut r, "rf --verbose", &mut context);
+        assert_eq!(context, 4);
+        assert!(!r.interface.as_str().contains("Error"));
+
+        type_line(&mut r, "alias", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("alias rr \"reboot; reboot\"\nalias rf \"led\"\n\n> "));
+        type_line(&mut r, "alias rr", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("\nalias rr \"reboot; reboot\"\n\n> "));
+
+        type_line(
+            &mut r,
+            "alias big \"reboot; reboot; reboot; reboot\"",
+            &mut context,
+        );
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: No room for alias \"big\""));
+
+        type_line(&mut r, "unalias rr", &mut context);
+        type_line(&mut r, "rr", &mut context);
+        assert!(r.interface.as_str().contains("Command \"rr\" not found"));
+        type_line(&mut r, "unalias rr", &mut context);
+        assert!(r.interface.as_str().contains("Error: No such alias \"rr\""));
+
+        // Built-in commands can't be hidden
+        type_line(&mut r, "alias alias reboot", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: \"alias\" can't be used as an alias"));
+        type_line(&mut r, "alias HELP reboot", &mut context);
+        assert!(r.interface.as_str().contains("Error: \"HELP\""));
+        type_line(&mut r, "alias x led", &mut context);
+        type_line(&mut r, "x --verbose", &mut context);
+        assert_eq!(context, 5);
+
+        // Aliases follow the runner's case setting, like commands do
+        type_line(&mut r, "X --verbose", &mut context);
+        assert!(r.interface.as_str().contains("Command \"X\" not found"));
+        assert_eq!(context, 5);
+        r.set_ignore_case(true);
+        type_line(&mut r, "X --verbose", &mut context);
+        assert_eq!(context, 6);
+        r.set_ignore_case(false);
+
+        let aliases = r.aliases().unwrap();
+        assert_eq!(aliases.get("rf"), Some("led"));
+        assert_eq!(aliases.define("rf", "reboot"), Ok(()));
+        assert_eq!(aliases.define("a;b", "reboot"), Err(Error::InvalidName));
+        assert_eq!(aliases.define("a b", "reboot"), Err(Error::InvalidName));
+        assert_eq!(aliases.define("a&&b", "reboot"), Err(Error::InvalidName));
+        assert_eq!(aliases.define("", "reboot"), Err(Error::InvalidName));
+        assert_eq!(aliases.define("unset", "reboot"), Err(Error::InvalidName));
+        assert_eq!(aliases.iter().count(), 2);
+    }
+
+    #[test]
+    fn store_limits() {
+        // There's room, but the length won't fit in the header
+        let mut buffer = [0u8; 70_000];
+        let mut aliases = alias::Aliases::new(&mut buffer);
+        assert_eq!(aliases.define("big", "reboot"), Ok(()));
+        let huge = [b'x'; 66_000];
+        let huge = core::str::from_utf8(&huge).unwrap();
+        assert_eq!(aliases.define("big", huge), Err(Error::TableFull));
+        assert_eq!(aliases.get("big"), Some("reboot"));
+        let long_name = &huge[0..256];
+        assert_eq!(aliases.define(long_name, "reboot"), Err(Error::TableFull));
+        assert_eq!(aliases.iter().count(), 1);
+    }
+
+    #[test]
+    fn repeat_and_watch() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+
+        // Off unless asked for
+        type_line(&mut r, "repeat 3 reboot", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Command \"repeat\" not found"));
+        assert_eq!(context, 0);
+        type_line(&mut r, "help", &mut context);
+        assert!(!r.interface.as_str().contains("repeat"));
+
+        r.set_repeat(true);
+        type_line(&mut r, "repeat 3 reboot", &mut context);
+        assert_eq!(context, 3);
+        type_line(&mut r, "repeat 3 fail && reboot", &mut context);
+        assert_eq!(context, 3);
+        assert_eq!(r.interface.as_str().matches("Failed!").count(), 1);
+        type_line(&mut r, "repeat lots reboot", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: \"lots\" is not a number"));
+
+        // Needs a clock
+        type_line(&mut r, "watch 250 reboot", &mut context);
+        assert_eq!(context, 3);
+        assert!(r.interface.as_str().contains("Command \"watch\" not found"));
+
+        // Starting from u32::MAX checks the clock can wrap
+        let mut ticker = Ticker(u32::MAX);
+        r.set_time_source(&mut ticker);
+        r.interface.polls_until_key = 5;
+        type_line(&mut r, "watch 250 reboot", &mut context);
+        assert_eq!(context, 4);
+        assert!(r.is_running());
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Every 250ms: reboot (press any key to stop)\n"));
+        while r.is_running() {
+            r.poll(&mut context);
+        }
+        assert_eq!(context, 5);
+        assert!(r.interface.as_str().ends_with("\n> "));
+
+        // Any key given to the runner stops it too
+        type_line(&mut r, "watch 250 reboot", &mut context);
+        assert_eq!(context, 6);
+        r.poll(&mut context);
+        r.poll(&mut context);
+        assert_eq!(context, 6);
+        r.input_byte(b'q', &mut context);
+        assert!(!r.is_running());
+        r.poll(&mut context);
+        assert_eq!(context, 6);
+
+        // It finishes with the status of the last run
+        let options = script::ScriptOptions::default();
+        r.interface.polls_until_key = 3;
+        assert_eq!(
+            r.run_script("watch 250 reboot", options, &mut context),
+            Ok(())
+        );
+        r.interface.polls_until_key = 3;
+        assert_eq!(
+            r.run_script("watch 250 fail", options, &mut context),
+            Err(script::ScriptError::Failed(1))
+        );
+    }
+
+    #[test]
+    fn items_win_over_builtins() {
+        const MENU: Menu<Output, u32> = Menu::new(
+            "root",
+            &[
+                &Item::new(
+                    "watch",
+                    ItemType::Callback {
+                        function: count,
+                        parameters: &[Parameter::Optional {
+                            parameter_name: "ms",
+                            help: None,
+                        }],
+                    },
+                ),
+                &Item::new(
+                    "set",
+                    ItemType::Callback {
+                        function: count,
+                        parameters: &[],
+                    },
+                ),
+            ],
+        );
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(MENU, &mut buffer, Output::new(), &mut context);
+        let mut ticker = Ticker(0);
+        r.set_time_source(&mut ticker);
+        let mut variable_buffer = [0u8; 64];
+        r.set_variables(variables::Variables::new(&mut variable_buffer));
+
+        type_line(&mut r, "watch 250", &mut context);
+        assert!(!r.is_running());
+        type_line(&mut r, "set", &mut context);
+        assert_eq!(context, 2);
+        type_line(&mut r, "help", &mut context);
+        assert_eq!(r.interface.as_str().matches("  watch").count(), 1);
+        assert!(r.interface.as_str().contains("  watch [ <ms> ]\n"));
+        assert!(!r.interface.as_str().contains("  set ["));
+        assert!(r.interface.as_str().contains("  unset <name>"));
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_interrupt(&INTERRUPT);
+
+        // Ctrl-C at the prompt throws the line away
+        r.interface.clear();
+        for b in b"reboot\x03\r" {
+            r.input_byte(*b, &mut context);
+        }
+        assert_eq!(context, 0);
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("reboot^C\n> \nInput was empty?\n\n> "));
+
+        // Ctrl-C stops the scan, and the rest of the line
+        r.interface.polls_until_key = 5;
+        r.interface.key = interrupt::CTRL_C;
+        type_line(&mut r, "scan; reboot", &mut context);
+        assert_eq!(context, 4);
+        assert!(r.interface.as_str().ends_with("\n^C\n\n> "));
+        assert!(!INTERRUPT.is_requested());
+
+        // An old request is forgotten, and an interrupt stops a script
+        let options = script::ScriptOptions {
+            echo: false,
+            on_error: script::OnError::Continue,
+        };
+        r.interface.polls_until_key = 3;
+        INTERRUPT.request();
+        assert_eq!(
+            r.run_script("reboot\nscan\nreboot\n", options, &mut context),
+            Err(script::ScriptError::Interrupted(2))
+        );
+        assert_eq!(context, 7);
+    }
+
+    #[test]
+    fn questions() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+
+        // The rest of the line is skipped
+        type_line(&mut r, "wipe; reboot", &mut context);
+        assert_eq!(context, 0);
+        assert!(r.interface.as_str().ends_with("\nReally wipe? [y/N] "));
+        type_line(&mut r, "Y", &mut context);
+        assert!(r.interface.as_str().ends_with("\nPassword: "));
+        type_line(&mut r, "hunter2", &mut context);
+        assert_eq!(context, 100);
+        assert_eq!(
+            r.interface.as_str(),
+            "\rPassword: *\rPassword: **\rPassword: ***\rPassword: ****\r\
+             Password: *****\rPassword: ******\rPassword: *******\n\n> "
+        );
+
+        type_line(&mut r, "wipe", &mut context);
+        type_line(&mut r, "", &mut context);
+        assert!(r.interface.as_str().ends_with("\nNot wiped\n\n> "));
+
+        // Ctrl-C gives up on the question
+        type_line(&mut r, "wipe", &mut context);
+        type_line(&mut r, "\x03reboot", &mut context);
+        assert_eq!(context, 101);
+        assert!(r.interface.as_str().starts_with("^CNot wiped\n"));
+
+        // Scripts can't answer, and tell the command they gave up
+        let options = script::ScriptOptions::default();
+        r.interface.clear();
+        assert_eq!(
+            r.run_script("wipe\nreboot\n", options, &mut context),
+            Err(script::ScriptError::Failed(1))
+        );
+        assert!(r.interface.as_str().contains("Not wiped\n"));
+        type_line(&mut r, "reboot", &mut context);
+        assert_eq!(context, 102);
+    }
+
+    #[test]
+    fn pending_commands() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+
+        type_line(&mut r, "flash; reboot", &mut context);
+        assert!(r.is_running());
+        assert!(r.interface.as_str().ends_with(
+            "> flash; reboot\nError: Skipped \"reboot\" as \"flash\" has not finished\n"
+        ));
+
+        // Input is ignored, and there's no prompt until it finishes
+        type_line(&mut r, "reboot", &mut context);
+        r.poll(&mut context);
+        r.poll(&mut context);
+        assert_eq!(r.interface.as_str(), "");
+        r.poll(&mut context);
+        assert!(!r.is_running());
+        assert_eq!(context, 3);
+        assert_eq!(r.interface.as_str(), "Flashed\n\n> ");
+        r.poll(&mut context);
+        assert_eq!(context, 3);
+
+        // Ctrl-C abandons it, and tells it so
+        type_line(&mut r, "flash", &mut context);
+        type_line(&mut r, "\x03", &mut context);
+        assert!(!r.is_running());
+        assert_eq!(context, 3);
+        assert!(r.interface.as_str().starts_with("^CFlash abandoned\n"));
+
+        // Scripts wait for it to finish
+        let options = script::ScriptOptions::default();
+        assert_eq!(
+            r.run_script("flash\nreboot\n", options, &mut context),
+            Ok(())
+        );
+        assert_eq!(context, 7);
+    }
+
+    fn check_password(_context: &mut u32, password: &str) -> bool {
+        password == "letmein"
+    }
+
+    const SERVICE_GATE: session::Gate<u32> = session::Gate {
+        name: "service",
+        prompt: "Password: ",
+        verify: check_password,
+        max_failures: 2,
+        lockout_ms: 300,
+    };
+
+    const GATED_MENU: Menu<Output, u32> = Menu::new(
+        "root",
+        &[&Item::new(
+            "service",
+            ItemType::Menu(
+                &Menu::new(
+                    "service",
+                    &[&Item::new(
+                        "read",
+                        ItemType::Callback {
+                            function: count,
+                            parameters: &[],
+                        },
+                    )],
+                )
+                .gate(&SERVICE_GATE),
+            ),
+        )],
+    );
+
+    #[test]
+    fn gated_menus() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(GATED_MENU, &mut buffer, Output::new(), &mut context);
+        let mut ticker = Ticker(0);
+        r.set_time_source(&mut ticker);
+
+        type_line(&mut r, "service", &mut context);
+        assert!(r.interface.as_str().ends_with("\nPassword: "));
+        type_line(&mut r, "wrong", &mut context);
+        assert!(r.interface.as_str().starts_with("\rPassword: *\r"));
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("\nError: Wrong password\n\n> "));
+
+        // Two wrong passwords lock it for a while
+        type_line(&mut r, "service", &mut context);
+        type_line(&mut r, "bad", &mut context);
+        type_line(&mut r, "service", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Too many wrong passwords"));
+        type_line(&mut r, "service", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Too many wrong passwords"));
+        type_line(&mut r, "service", &mut context);
+        assert!(r.interface.as_str().ends_with("\nPassword: "));
+        type_line(&mut r, "letmein", &mut context);
+        assert!(r.interface.as_str().ends_with("\nservice> "));
+        type_line(&mut r, "read", &mut context);
+        assert_eq!(context, 1);
+
+        // Still logged in after leaving
+        type_line(&mut r, "exit", &mut context);
+        type_line(&mut r, "service", &mut context);
+        assert!(r.interface.as_str().ends_with("\nservice> "));
+        type_line(&mut r, "logout", &mut context);
+        assert!(r.interface.as_str().ends_with("\n> "));
+        type_line(&mut r, "logout", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Command \"logout\" not found"));
+        type_line(&mut r, "service", &mut context);
+        assert!(r.interface.as_str().ends_with("\nPassword: "));
+        type_line(&mut r, "\x03", &mut context);
+        assert!(r.interface.as_str().starts_with("^C\n> "));
+    }
+
+    const GATE_A: session::Gate<u32> = session::Gate {
+        name: "a",
+        prompt: "A password: ",
+        verify: |_context, password| password == "aaa",
+        max_failures: 2,
+        lockout_ms: 10_000,
+    };
+
+    const GATE_B: session::Gate<u32> = session::Gate {
+        name: "b",
+        prompt: "B password: ",
+        verify: |_context, password| password == "bbb",
+        max_failures: 3,
+        lockout_ms: 0,
+    };
+
+    #[test]
+    fn separate_gates() {
+        const MENU: Menu<Output, u32> = Menu::new(
+            "root",
+            &[
+                &Item::new("a", ItemType::Menu(&Menu::new("a", &[]).gate(&GATE_A))),
+                &Item::new("b", ItemType::Menu(&Menu::new("b", &[]).gate(&GATE_B))),
+                &Item::new(
+                    "also_a",
+                    ItemType::Menu(&Menu::new("also_a", &[]).gate(&GATE_A)),
+                ),
+            ],
+        );
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(MENU, &mut buffer, Output::new(), &mut context);
+
+        type_line(&mut r, "a", &mut context);
+        type_line(&mut r, "aaa", &mut context);
+        assert!(r.interface.as_str().ends_with("\na> "));
+        type_line(&mut r, "exit", &mut context);
+
+        // Gate B still wants its own password, and not gate A's
+        type_line(&mut r, "b", &mut context);
+        assert!(r.interface.as_str().ends_with("\nB password: "));
+        type_line(&mut r, "aaa", &mut context);
+        assert!(r.interface.as_str().contains("Error: Wrong password"));
+        type_line(&mut r, "b", &mut context);
+        type_line(&mut r, "bbb", &mut context);
+        assert!(r.interface.as_str().ends_with("\nb> "));
+        type_line(&mut r, "exit", &mut context);
+
+        // Both are remembered, as is another menu behind gate A
+        type_line(&mut r, "a", &mut context);
+        assert!(r.interface.as_str().ends_with("\na> "));
+        type_line(&mut r, "exit", &mut context);
+        type_line(&mut r, "also_a", &mut context);
+        assert!(r.interface.as_str().ends_with("\nalso_a> "));
+        type_line(&mut r, "exit", &mut context);
+        type_line(&mut r, "b", &mut context);
+        assert!(r.interface.as_str().ends_with("\nb> "));
+
+        // Until they log out
+        type_line(&mut r, "logout", &mut context);
+        type_line(&mut r, "a", &mut context);
+        assert!(r.interface.as_str().ends_with("\nA password: "));
+
+        // Wrong passwords lock every menu behind gate A, but not gate B
+        let mut ticker = Ticker(0);
+        r.set_clock(&mut ticker);
+        type_line(&mut r, "bbb", &mut context);
+        type_line(&mut r, "a", &mut context);
+        type_line(&mut r, "bbb", &mut context);
+        type_line(&mut r, "also_a", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Too many wrong passwords"));
+        type_line(&mut r, "b", &mut context);
+        assert!(r.interface.as_str().ends_with("\nB password: "));
+        type_line(&mut r, "bbb", &mut context);
+        assert!(r.interface.as_str().ends_with("\nb> "));
+    }
+
+    fn check_enable(_context: &mut u32, level: u8, password: &str) -> bool {
+        level <= 2 && password == "secret"
+    }
+
+    fn leave_config(_menu: &Menu<Output, u32>, _interface: &mut Output, context: &mut u32) {
+        *context += 10;
+    }
+
+    const PRIVILEGED_MENU: Menu<Output, u32> = Menu::new(
+        "root",
+        &[
+            &Item::new(
+                "reset",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[],
+                },
+            )
+            .privilege(2),
+            &Item::new(
+                "erase",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[Parameter::Optional {
+                        parameter_name: "sector",
+                        help: None,
+                    }],
+                },
+            )
+            .confirm(),
+            &Item::new(
+                "config",
+                ItemType::Menu(
+                    &Menu::new(
+                        "config",
+                        &[&Item::new(
+                            "wifi",
+                            ItemType::Callback {
+                                function: count,
+                                parameters: &[
+                                    Parameter::Mandatory {
+                                        parameter_name: "ssid",
+                                        help: None,
+                                    },
+                                    Parameter::Secret(&Parameter::Optional {
+                                        parameter_name: "psk",
+                                        help: None,
+                                    }),
+                                    Parameter::Secret(&Parameter::NamedValue {
+                                        parameter_name: "key",
+                                        argument_name: "KEY",
+                                        help: None,
+                                    }),
+                                ],
+                            },
+                        )],
+                    )
+                    .exit(leave_config)
+                    .privilege(1),
+                ),
+            ),
+        ],
+    );
+
+    #[test]
+    fn privilege_levels() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(PRIVILEGED_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_enable(check_enable);
+
+        // Nothing above level 0 can be seen or used
+        type_line(&mut r, "help", &mut context);
+        assert!(!r.interface.as_str().contains("reset"));
+        assert!(!r.interface.as_str().contains("config"));
+        assert!(r.interface.as_str().contains("  enable <level>"));
+        assert!(!r.interface.as_str().contains("disable"));
+        type_line(&mut r, "reset", &mut context);
+        assert!(r.interface.as_str().contains("Command \"reset\" not found"));
+        type_line(&mut r, "res\t", &mut context);
+        assert_eq!(context, 0);
+
+        type_line(&mut r, "enable 2", &mut context);
+        assert!(r.interface.as_str().ends_with("\nPassword: "));
+        type_line(&mut r, "wrong", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("\nError: Wrong password\n\n> "));
+        type_line(&mut r, "enable 3", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        assert!(r.interface.as_str().contains("Error: Wrong password"));
+
+        type_line(&mut r, "enable 2", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        type_line(&mut r, "res\t", &mut context);
+        assert_eq!(context, 1);
+        type_line(&mut r, "help", &mut context);
+        assert!(r.interface.as_str().contains("  disable"));
+
+        // Going down a level doesn't need a password
+        type_line(&mut r, "enable 1", &mut context);
+        assert!(r.interface.as_str().ends_with("\n> "));
+        type_line(&mut r, "reset", &mut context);
+        assert_eq!(context, 1);
+        type_line(&mut r, "config", &mut context);
+        assert!(r.interface.as_str().ends_with("\nconfig> "));
+
+        // Dropping privileges leaves menus we can no longer use
+        type_line(&mut r, "disable", &mut context);
+        assert!(r.interface.as_str().ends_with("\n> "));
+        assert_eq!(context, 11);
+        type_line(&mut r, "config", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Command \"config\" not found"));
+
+        // So does going down with `enable`
+        type_line(&mut r, "enable 1", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        type_line(&mut r, "config", &mut context);
+        type_line(&mut r, "enable 0", &mut context);
+        assert!(r.interface.as_str().ends_with("\n> "));
+        assert_eq!(context, 21);
+        type_line(&mut r, "wifi home", &mut context);
+        assert!(r.interface.as_str().contains("Command \"wifi\" not found"));
+        assert_eq!(context, 21);
+    }
+
+    /// The id of the last command recorded by `audit_finished`
+    static BEFORE_ID: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+    fn audit_wifi(context: &mut u32, record: &audit::Record) {
+        match (record.command, record.stage) {
+            ("enable", audit::Stage::Rejected(reason)) => {
+                assert_eq!(reason, "wrong password");
+                assert_eq!(record.args, ["1"]);
+                assert_eq!(record.privilege, 0);
+                *context += 100_000_000;
+            }
+            ("enable", audit::Stage::After(Status::Success)) => {
+                assert_eq!(record.args, ["1"]);
+                assert_eq!(record.privilege, 1);
+                *context += 10_000;
+            }
+            ("config", audit::Stage::Entered) => {
+                assert_eq!(record.path, [("config", None)]);
+                *context += 100_000;
+            }
+            ("disable", audit::Stage::After(Status::Success)) => {
+                assert_eq!(record.privilege, 0);
+                *context += 10_000_000;
+            }
+            ("wifi", stage) => {
+                assert_eq!(record.path, [("config", None)]);
+                assert_eq!(record.privilege, 1);
+                match stage {
+                    audit::Stage::Before => {
+                        assert_eq!(record.args, ["home", "****", "****"]);
+                        *context += 100;
+                    }
+                    audit::Stage::After(status) => {
+                        assert_eq!(status, Status::Success);
+                        assert_eq!(*context % 100, 1);
+                        *context += 1000;
                     }
-                }
-                if !found {
-                    writeln!(interface, "Command {:?} not found. Try 'help'.", cmd).unwrap();
+                    audit::Stage::Rejected(reason) => {
+                        assert_eq!(reason, "insufficient arguments");
+                        assert!(record.args.is_empty());
+                        *context += 1_000_000;
+                    }
+                    _ => panic!("wifi is not resumable"),
                 }
             }
-        } else {
-            writeln!(interface, "Input was empty?").unwrap();
+            (command, stage) => panic!("{:?} should not be recorded as {:?}", command, stage),
         }
     }
 
-    fn print_short_help(&mut self, interface: &mut I, item: &Item<I, T>) {
-        let mut has_options = false;
-        match item.item_type {
-            ItemType::Callback { parameters, .. } => {
-                write!(interface, "  {}", item.command).unwrap();
-                if !parameters.is_empty() {
-                    for param in parameters.iter() {
-                        match param {
-                            Parameter::Mandatory { parameter_name, .. } => {
-                                write!(interface, " <{}>", parameter_name).unwrap();
-                            }
-                            Parameter::Optional { parameter_name, .. } => {
-                                write!(interface, " [ <{}> ]", parameter_name).unwrap();
-                            }
-                            Parameter::Named { .. } => {
-                                has_options = true;
-                            }
-                            Parameter::NamedValue { .. } => {
-                                has_options = true;
-                            }
-                        }
-                    }
-                }
+    #[test]
+    fn audit_hook() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(PRIVILEGED_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_enable(check_enable);
+        r.set_audit(audit_wifi);
+        type_line(&mut r, "enable 1", &mut context);
+        type_line(&mut r, "hunter2", &mut context);
+        assert_eq!(context, 100_000_000);
+        type_line(&mut r, "enable 1", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        type_line(&mut r, "config", &mut context);
+        type_line(&mut r, "wifi home hunter2 --key=abc", &mut context);
+        assert_eq!(context, 100_111_101);
+        // So are commands which can't run
+        type_line(&mut r, "wifi", &mut context);
+        assert_eq!(context, 101_111_101);
+        type_line(&mut r, "disable", &mut context);
+        // Leaving `config` counts too
+        assert_eq!(context, 111_111_111);
+    }
+
+    fn audit_finished(context: &mut u32, record: &audit::Record) {
+        match (record.command, record.stage) {
+            ("read", audit::Stage::After(_)) => {
+                assert_eq!(record.path, [("ch", Some(*context as usize))]);
             }
-            ItemType::Menu(_menu) => {
-                write!(interface, "  {}", item.command).unwrap();
+            (_, audit::Stage::Before) => {
+                BEFORE_ID.store(record.id, core::sync::atomic::Ordering::Relaxed);
             }
-            ItemType::_Dummy => {
-                write!(interface, "  {}", item.command).unwrap();
+            (_, audit::Stage::Finished(status)) => {
+                let before_id = BEFORE_ID.load(core::sync::atomic::Ordering::Relaxed);
+                assert_eq!(record.id, before_id);
+                *context += if status == Status::Success {
+                    1000
+                } else {
+                    10000
+                };
             }
+            _ => {}
         }
-        if has_options {
-            write!(interface, " [OPTIONS...]").unwrap();
-        }
-        writeln!(interface).unwrap();
     }
 
-    fn print_long_help(&mut self, interface: &mut I, item: &Item<I, T>) {
-        writeln!(interface, "SUMMARY:").unwrap();
-        match item.item_type {
-            ItemType::Callback { parameters, .. } => {
-                write!(interface, "  {}", item.command).unwrap();
-                if !parameters.is_empty() {
-                    for param in parameters.iter() {
-                        match param {
-                            Parameter::Mandatory { parameter_name, .. } => {
-                                write!(interface, " <{}>", parameter_name).unwrap();
-                            }
-                            Parameter::Optional { parameter_name, .. } => {
-                                write!(interface, " [ <{}> ]", parameter_name).unwrap();
-                            }
-                            Parameter::Named { parameter_name, .. } => {
-                                write!(interface, " [ --{} ]", parameter_name).unwrap();
-                            }
-                            Parameter::NamedValue {
-                                parameter_name,
-                                argument_name,
-                                ..
-                            } => {
-                                write!(interface, " [ --{}={} ]", parameter_name, argument_name)
-                                    .unwrap();
-                            }
-                        }
-                    }
-                    writeln!(interface, "\n\nPARAMETERS:").unwrap();
-                    let default_help = "Undocumented option";
-                    for param in parameters.iter() {
-                        match param {
-                            Parameter::Mandatory {
-                                parameter_name,
-                                help,
-                            } => {
-                                writeln!(
-                                    interface,
-                                    "  <{0}>\n    {1}\n",
-                                    parameter_name,
-                                    help.unwrap_or(default_help),
-                                )
-                                .unwrap();
-                            }
-                            Parameter::Optional {
-                                parameter_name,
-                                help,
-                            } => {
-                                writeln!(
-                                    interface,
-                                    "  <{0}>\n    {1}\n",
-                                    parameter_name,
-                                    help.unwrap_or(default_help),
-                                )
-                                .unwrap();
-                            }
-                            Parameter::Named {
-                                parameter_name,
-                                help,
-                            } => {
-                                writeln!(
-                                    interface,
-                                    "  --{0}\n    {1}\n",
-                                    parameter_name,
-                                    help.unwrap_or(default_help),
-                                )
-                                .unwrap();
-                            }
-                            Parameter::NamedValue {
-                                parameter_name,
-                                argument_name,
-                                help,
-                            } => {
-                                writeln!(
-                                    interface,
-                                    "  --{0}={1}\n    {2}\n",
-                                    parameter_name,
-                                    argument_name,
-                                    help.unwrap_or(default_help),
-                                )
-                                .unwrap();
-                            }
-                        }
-                    }
-                }
-            }
-            ItemType::Menu(_menu) => {
-                write!(interface, "  {}", item.command).unwrap();
-            }
-            ItemType::_Dummy => {
-                write!(interface, "  {}", item.command).unwrap();
+    #[test]
+    fn audit_resumed() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_audit(audit_finished);
+
+        // Recorded once the question has been answered
+        type_line(&mut r, "wipe", &mut context);
+        type_line(&mut r, "y", &mut context);
+        assert_eq!(context, 0);
+        type_line(&mut r, "hunter2", &mut context);
+        assert_eq!(context, 1100);
+        type_line(&mut r, "wipe", &mut context);
+        type_line(&mut r, "n", &mut context);
+        assert_eq!(context, 11100);
+
+        // And once it stops running
+        context = 1;
+        type_line(&mut r, "flash", &mut context);
+        r.poll(&mut context);
+        assert_eq!(context, 2);
+        r.poll(&mut context);
+        assert_eq!(context, 1003);
+
+        // Generated sub-menus say which one they are
+        type_line(&mut r, "ch3", &mut context);
+        type_line(&mut r, "read", &mut context);
+        assert_eq!(context, 3);
+    }
+
+    #[test]
+    fn secret_arguments() {
+        use noline::history::History;
+
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(PRIVILEGED_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_enable(check_enable);
+        type_line(&mut r, "enable 1", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        type_line(&mut r, "config", &mut context);
+
+        type_line(&mut r, "wifi home hunter2 --key=abc", &mut context);
+        assert!(!r.interface.as_str().contains("hunter"));
+        assert!(!r.interface.as_str().contains("abc"));
+        assert!(r
+            .interface
+            .as_str()
+            .contains("\rconfig> wifi home ******* --key=***\n"));
+        assert_eq!(context, 1);
+        type_line(&mut r, "wifi home; wifi  away  p4ss", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("\rconfig> wifi home; wifi  away  ****\n"));
+
+        // Lines with secrets are kept out of the history
+        let mut history_buffer = [0u8; 64];
+        let mut history = history::SkipSecrets::new(
+            noline::history::SliceHistory::new(&mut history_buffer),
+            &PRIVILEGED_MENU,
+        );
+        assert_eq!(history.add_entry("config"), Ok(()));
+        assert_eq!(history.add_entry("WIFI home hunter2"), Ok(()));
+        assert_eq!(history.add_entry("reset; wifi home --key=abc"), Ok(()));
+        assert_eq!(history.number_of_entries(), 1);
+        history.add_entry("wifi home").unwrap();
+        history.add_entry("erase -y 3").unwrap();
+        assert_eq!(history.number_of_entries(), 3);
+    }
+
+    #[test]
+    fn styling() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_theme(style::Theme::DEFAULT.if_supported(Some("xterm"), true));
+
+        type_line(&mut r, "help led", &mut context);
+        assert!(r.interface.as_str().contains("\x1b[1mSUMMARY:\x1b[0m\n"));
+        assert!(r
+            .interface
+            .as_str()
+            .contains("\n\n\x1b[1mPARAMETERS:\x1b[0m\n"));
+        assert!(r.interface.as_str().ends_with("\n\x1b[1;32m> \x1b[0m"));
+        type_line(&mut r, "nothing", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("\x1b[31mCommand \"nothing\" not found. Try 'help'.\x1b[0m\n"));
+
+        assert_eq!(style::Theme::DEFAULT.if_supported(Some("dumb"), true), None);
+        assert_eq!(style::Theme::DEFAULT.if_supported(None, true), None);
+        assert_eq!(
+            style::Theme::DEFAULT.if_supported(Some("xterm"), false),
+            None
+        );
+        r.set_theme(None);
+        type_line(&mut r, "nothing", &mut context);
+        assert!(!r.interface.as_str().contains('\x1b'));
+    }
+
+    fn host_prompt(
+        out: &mut dyn core::fmt::Write,
+        info: &prompt::PromptInfo,
+        context: &u32,
+    ) -> core::fmt::Result {
+        write!(out, "dev{:02}:", context)?;
+        for (label, index) in info.path {
+            write!(out, "/{}", label)?;
+            if let Some(index) = index {
+                write!(out, "{}", index)?;
             }
         }
-        if let Some(help) = item.help {
-            writeln!(interface, "\n\nDESCRIPTION:\n{}", help).unwrap();
+        if info.last_status == Status::Failure {
+            write!(out, " [ERR]")?;
         }
+        write!(out, "{} ", if info.privilege > 0 { '#' } else { '>' })
     }
 
-    fn call_function(
-        interface: &mut I,
-        context: &mut T,
-        callback_function: ItemCallbackFn<I, T>,
-        parameters: &[Parameter],
-        parent_menu: &Menu<I, T>,
-        item: &Item<I, T>,
-        command: &str,
-    ) {
-        let mandatory_parameter_count = parameters
-            .iter()
-            .filter(|p| matches!(p, Parameter::Mandatory { .. }))
-            .count();
-        let positional_parameter_count = parameters
-            .iter()
-            .filter(|p| matches!(p, Parameter::Mandatory { .. } | Parameter::Optional { .. }))
-            .count();
-        if command.len() >= item.command.len() {
-            // Maybe arguments
-            let mut argument_buffer: [&str; 16] = [""; 16];
-            let mut argument_count = 0;
-            let mut positional_arguments = 0;
-            for (slot, arg) in argument_buffer
-                .iter_mut()
-                .zip(command[item.command.len()..].split_whitespace())
-            {
-                *slot = arg;
-                argument_count += 1;
-                if let Some(tail) = arg.strip_prefix("--") {
-                    // Validate named argument
-                    let mut found = false;
-                    for param in parameters.iter() {
-                        match param {
-                            Parameter::Named { parameter_name, .. } => {
-                                if tail == *parameter_name {
-                                    found = true;
-                                    break;
-                                }
-                            }
-                            Parameter::NamedValue { parameter_name, .. } => {
-                                if arg.contains('=') {
-                                    if let Some(given_name) = tail.split('=').next() {
-                                        if given_name == *parameter_name {
-                                            found = true;
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {
-                                // Ignore
-                            }
-                        }
-                    }
-                    if !found {
-                        writeln!(interface, "Error: Did not understand {:?}", arg).unwrap();
-                        return;
-                    }
-                } else {
-                    positional_arguments += 1;
-                }
-            }
-            if positional_arguments < mandatory_parameter_count {
-                writeln!(interface, "Error: Insufficient arguments given").unwrap();
-            } else if positional_arguments > positional_parameter_count {
-                writeln!(interface, "Error: Too many arguments given").unwrap();
-            } else {
-                callback_function(
-                    parent_menu,
-                    item,
-                    &argument_buffer[0..argument_count],
-                    interface,
-                    context,
-                );
+    #[test]
+    fn custom_prompt() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(PRIVILEGED_MENU, &mut buffer, Output::new(), &mut context);
+        r.set_enable(check_enable);
+        r.set_prompt(host_prompt);
+
+        type_line(&mut r, "", &mut context);
+        assert!(r.interface.as_str().ends_with("\ndev00:> "));
+        type_line(&mut r, "enable 1", &mut context);
+        assert!(r.interface.as_str().ends_with("\nPassword: "));
+        type_line(&mut r, "secret", &mut context);
+        assert!(r.interface.as_str().ends_with("\ndev00:# "));
+        type_line(&mut r, "config", &mut context);
+        assert!(r.interface.as_str().ends_with("\ndev00:/config# "));
+        type_line(&mut r, "nothing", &mut context);
+        assert!(r.interface.as_str().ends_with("\ndev00:/config [ERR]# "));
+
+        // Prompts for `noline` are cut short at a whole character
+        let mut text = [0u8; 3];
+        let mut out = PromptBuffer {
+            buffer: &mut text,
+            used: 0,
+        };
+        assert!(core::fmt::Write::write_str(&mut out, "hé!").is_err());
+        assert_eq!(&text, b"h\xc3\xa9");
+        let mut out = PromptBuffer {
+            buffer: &mut text,
+            used: 1,
+        };
+        assert!(core::fmt::Write::write_str(&mut out, "éé").is_err());
+        assert_eq!(out.used, 3);
+    }
+
+    #[test]
+    fn confirmation() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(PRIVILEGED_MENU, &mut buffer, Output::new(), &mut context);
+
+        type_line(&mut r, "erase 3", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("\nReally run 'erase'? [y/N] "));
+        type_line(&mut r, "", &mut context);
+        assert!(r.interface.as_str().ends_with("\n\n> "));
+        assert_eq!(context, 0);
+        type_line(&mut r, "erase 3 && erase", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Skipped \"erase\" as \"erase 3\" has not finished"));
+        type_line(&mut r, "n", &mut context);
+        assert_eq!(context, 0);
+        r.set_repeat(true);
+        type_line(&mut r, "repeat 3 erase", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Skipped 2 more runs as \"erase\" has not finished"));
+        type_line(&mut r, "n", &mut context);
+        assert_eq!(context, 0);
+
+        type_line(&mut r, "erase 3", &mut context);
+        type_line(&mut r, "yes", &mut context);
+        assert_eq!(context, 1);
+        type_line(&mut r, "erase --yes 3", &mut context);
+        type_line(&mut r, "erase -y", &mut context);
+        assert_eq!(context, 3);
+        type_line(&mut r, "erase 3 4 -y", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Too many arguments given"));
+
+        // Scripts have to say yes up front
+        let options = script::ScriptOptions::default();
+        assert_eq!(
+            r.run_script("erase", options, &mut context),
+            Err(script::ScriptError::Failed(1))
+        );
+        assert_eq!(r.run_script("erase -y", options, &mut context), Ok(()));
+        assert_eq!(context, 4);
+    }
+
+    /// An interface which can't be read from
+    struct WriteOnly(Output);
+
+    impl embedded_io::ErrorType for WriteOnly {
+        type Error = core::convert::Infallible;
+    }
+
+    impl embedded_io::Write for WriteOnly {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn clock_without_read() {
+        const MENU: Menu<WriteOnly, u32> = Menu::new(
+            "root",
+            &[&Item::new(
+                "service",
+                ItemType::Menu(&Menu::new("service", &[]).gate(&session::Gate {
+                    name: "service",
+                    prompt: "Password: ",
+                    verify: check_password,
+                    max_failures: 1,
+                    lockout_ms: 1000,
+                })),
+            )],
+        );
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(MENU, &mut buffer, WriteOnly(Output::new()), &mut context);
+        let mut ticker = Ticker(0);
+        r.set_clock(&mut ticker);
+        for line in ["service", "wrong", "service"] {
+            r.interface.0.clear();
+            for b in line.bytes().chain([b'\r']) {
+                r.input_byte(b, &mut context);
             }
-        } else {
-            // Definitely no arguments
-            if mandatory_parameter_count == 0 {
-                callback_function(parent_menu, item, &[], interface, context);
-            } else {
-                writeln!(interface, "Error: Insufficient arguments given").unwrap();
+        }
+        assert!(r
+            .interface
+            .0
+            .as_str()
+            .contains("Error: Too many wrong passwords"));
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(PRIVILEGED_MENU, &mut buffer, Output::new(), &mut context);
+        let mut ticker = Ticker(0);
+        r.set_clock(&mut ticker);
+        r.set_enable(check_enable);
+        r.set_idle_timeout(1000);
+
+        // Nothing happens at the root menu at level 0
+        r.interface.clear();
+        for _ in 0..20 {
+            r.poll(&mut context);
+        }
+        assert_eq!(r.interface.as_str(), "");
+
+        // A clock on its own doesn't give us `watch`
+        type_line(&mut r, "watch 100 reset", &mut context);
+        assert!(r.interface.as_str().contains("Command \"watch\" not found"));
+
+        type_line(&mut r, "enable 1", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        type_line(&mut r, "config", &mut context);
+        assert!(r.interface.as_str().ends_with("\nconfig> "));
+        r.interface.clear();
+        r.poll(&mut context);
+        assert_eq!(r.interface.as_str(), "");
+        for _ in 0..10 {
+            r.poll(&mut context);
+        }
+        assert_eq!(r.interface.as_str(), "\nSession timed out\n\n> ");
+        assert_eq!(context, 10);
+        type_line(&mut r, "config", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Command \"config\" not found"));
+
+        // Typing keeps the session alive, and a half-typed line is thrown
+        // away when it times out
+        type_line(&mut r, "enable 1", &mut context);
+        type_line(&mut r, "secret", &mut context);
+        for b in "confi".bytes() {
+            r.input_byte(b, &mut context);
+            for _ in 0..3 {
+                r.poll(&mut context);
             }
         }
+        for _ in 0..10 {
+            r.poll(&mut context);
+        }
+        type_line(&mut r, "g", &mut context);
+        assert!(r.interface.as_str().contains("Command \"g\" not found"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn variables() {
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(TEST_MENU, &mut buffer, Output::new(), &mut context);
+        let mut variable_buffer = [0u8; 64];
+        r.set_variables(variables::Variables::new(&mut variable_buffer));
 
-    fn dummy(
-        _menu: &Menu<(), u32>,
-        _item: &Item<(), u32>,
-        _args: &[&str],
-        _interface: &mut (),
-        _context: &mut u32,
-    ) {
+        type_line(&mut r, "set cmd reboot; $cmd", &mut context);
+        assert_eq!(context, 1);
+        type_line(&mut r, "set twice \"$cmd; $cmd\"", &mut context);
+        type_line(&mut r, "set", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .ends_with("\ncmd=\"reboot\"\ntwice=\"reboot; reboot\"\n\n> "));
+
+        // The separators come from the line as typed, not from variables
+        type_line(&mut r, "$twice", &mut context);
+        assert_eq!(context, 1);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Command \"reboot;\" not found"));
+
+        type_line(
+            &mut r,
+            "fail; set result $?; reboot; set ok $?",
+            &mut context,
+        );
+        assert_eq!(context, 2);
+        assert_eq!(r.variables().unwrap().get("result"), Some("1"));
+        assert_eq!(r.variables().unwrap().get("ok"), Some("0"));
+
+        type_line(&mut r, "led $nothing", &mut context);
+        assert_eq!(context, 2);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: No such variable \"nothing\""));
+
+        type_line(&mut r, "unset cmd", &mut context);
+        type_line(&mut r, "set cmd", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: No such variable \"cmd\""));
+        type_line(&mut r, "set a-b 1", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Bad variable name \"a-b\""));
+        type_line(&mut r, "set cost $$5", &mut context);
+        assert_eq!(r.variables().unwrap().get("cost"), Some("$5"));
     }
 
     #[test]
     fn find_arg_mandatory() {
-        let item = Item {
-            command: "dummy",
-            help: None,
-            item_type: ItemType::Callback {
+        let item = Item::new(
+            "dummy",
+            ItemType::Callback {
                 function: dummy,
                 parameters: &[
                     Parameter::Mandatory {
@@ -813,7 +5317,7 @@ mod tests {
                     },
                 ],
             },
-        };
+        );
         assert_eq!(
             argument_finder(&item, &["a", "b", "c"], "foo"),
             Ok(Some("a"))
@@ -835,10 +5339,9 @@ mod tests {
 
     #[test]
     fn find_arg_optional() {
-        let item = Item {
-            command: "dummy",
-            help: None,
-            item_type: ItemType::Callback {
+        let item = Item::new(
+            "dummy",
+            ItemType::Callback {
                 function: dummy,
                 parameters: &[
                     Parameter::Mandatory {
@@ -855,7 +5358,7 @@ mod tests {
                     },
                 ],
             },
-        };
+        );
         assert_eq!(
             argument_finder(&item, &["a", "b", "c"], "foo"),
             Ok(Some("a"))
@@ -879,10 +5382,9 @@ mod tests {
 
     #[test]
     fn find_arg_named() {
-        let item = Item {
-            command: "dummy",
-            help: None,
-            item_type: ItemType::Callback {
+        let item = Item::new(
+            "dummy",
+            ItemType::Callback {
                 function: dummy,
                 parameters: &[
                     Parameter::Mandatory {
@@ -899,7 +5401,7 @@ mod tests {
                     },
                 ],
             },
-        };
+        );
         assert_eq!(
             argument_finder(&item, &["a", "--bar", "--baz"], "foo"),
             Ok(Some("a"))
@@ -923,10 +5425,9 @@ mod tests {
 
     #[test]
     fn find_arg_namedvalue() {
-        let item = Item {
-            command: "dummy",
-            help: None,
-            item_type: ItemType::Callback {
+        let item = Item::new(
+            "dummy",
+            ItemType::Callback {
                 function: dummy,
                 parameters: &[
                     Parameter::Mandatory {
@@ -944,7 +5445,7 @@ mod tests {
                     },
                 ],
             },
-        };
+        );
         assert_eq!(
             argument_finder(&item, &["a", "--bar", "--baz"], "foo"),
             Ok(Some("a"))
@@ -985,4 +5486,163 @@ mod tests {
         // Missing named
         assert_eq!(argument_finder(&item, &["a"], "baz"), Ok(None));
     }
+
+    fn led(
+        _menu: &Menu<Output, u32>,
+        item: &Item<Output, u32>,
+        args: &[&str],
+        _interface: &mut Output,
+        context: &mut u32,
+    ) -> Status {
+        if argument_finder(item, args, "verbose") == Ok(Some("")) {
+            *context += 1;
+        }
+        if let Ok(Some(level)) = argument_finder(item, args, "level") {
+            *context += 10 * level.parse::<u32>().unwrap();
+        }
+        Status::Success
+    }
+
+    #[test]
+    fn ignore_case() {
+        const MENU: Menu<Output, u32> = Menu::new(
+            "root",
+            &[&Item::new(
+                "led",
+                ItemType::Callback {
+                    function: led,
+                    parameters: &[
+                        Parameter::Named {
+                            parameter_name: "verbose",
+                            help: None,
+                        },
+                        Parameter::NamedValue {
+                            parameter_name: "level",
+                            argument_name: "INT",
+                            help: None,
+                        },
+                    ],
+                },
+            )],
+        );
+        let mut context = 0;
+        let mut buffer = [0u8; 256];
+        let mut r = Runner::new(MENU, &mut buffer, Output::new(), &mut context);
+
+        type_line(&mut r, "LED --VERBOSE", &mut context);
+        assert!(r.interface.as_str().contains("Command \"LED\" not found"));
+        assert_eq!(context, 0);
+
+        // Callbacks see the names as they were declared
+        r.set_ignore_case(true);
+        type_line(&mut r, "LED --VERBOSE --Level=3", &mut context);
+        assert_eq!(context, 31);
+        type_line(&mut r, "led --verbose", &mut context);
+        assert_eq!(context, 32);
+        type_line(&mut r, "Led --VERBOSITY", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("Error: Did not understand \"--VERBOSITY\""));
+        assert_eq!(context, 32);
+
+        // There's only so much room to re-write them
+        let mut line = [b'0'; 143];
+        line[0..12].copy_from_slice(b"led --LEVEL=");
+        line[142] = b'1';
+        // The line is re-drawn after every key, so don't keep all that
+        for b in line {
+            r.interface.clear();
+            r.input_byte(b, &mut context);
+        }
+        r.input_byte(b'\r', &mut context);
+        assert!(r.interface.as_str().contains("Error: Arguments too long"));
+        assert_eq!(context, 32);
+        line[6..11].copy_from_slice(b"level");
+        for b in line {
+            r.interface.clear();
+            r.input_byte(b, &mut context);
+        }
+        r.input_byte(b'\r', &mut context);
+        assert_eq!(context, 42);
+    }
+
+    #[test]
+    fn newline_translation() {
+        let mut out = newline::Translate::new(Output::new(), newline::Newline::CrLf);
+        write!(out, "one\ntwo\r\n\nthree").unwrap();
+        // A `\r` and `\n` written separately are still only one newline
+        out.write_all(b"\r").unwrap();
+        out.write_all(b"\n").unwrap();
+        assert_eq!(out.inner().as_str(), "one\r\ntwo\r\n\r\nthree\r\n");
+
+        let mut out = newline::Translate::new(Output::new(), newline::Newline::Lf);
+        write!(out, "one\ntwo\r\n").unwrap();
+        assert_eq!(out.inner().as_str(), "one\ntwo\r\n");
+
+        // Everything the runner prints goes through it
+        const MENU: Menu<newline::Translate<Output>, u32> = Menu::new("root", &[]);
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let interface = newline::Translate::new(Output::new(), newline::Newline::CrLf);
+        let mut r = Runner::new(MENU, &mut buffer, interface, &mut context);
+        r.interface.inner_mut().clear();
+        for b in "nope\r".bytes() {
+            r.input_byte(b, &mut context);
+        }
+        let output = r.interface.inner().as_str();
+        assert!(output.ends_with("\r\nCommand \"nope\" not found. Try 'help'.\r\n\r\n> "));
+        let bytes = output.as_bytes();
+        assert!((0..bytes.len()).all(|i| bytes[i] != b'\n' || (i > 0 && bytes[i - 1] == b'\r')));
+    }
+
+    #[test]
+    fn help_wrapping() {
+        const MENU: Menu<Output, u32> = Menu::new(
+            "root",
+            &[&Item::new(
+                "foo",
+                ItemType::Callback {
+                    function: count,
+                    parameters: &[Parameter::Mandatory {
+                        parameter_name: "a",
+                        help: Some("This is the help text for 'a', which is long"),
+                    }],
+                },
+            )
+            .help(
+                "Makes a foo appear.
+
+This is some extensive help text,
+which goes on for a while.
+",
+            )],
+        );
+        let mut context = 0;
+        let mut buffer = [0u8; 64];
+        let mut r = Runner::new(MENU, &mut buffer, Output::new(), &mut context);
+
+        // Printed as written by default
+        type_line(&mut r, "help foo", &mut context);
+        assert!(r
+            .interface
+            .as_str()
+            .contains("  <a>\n    This is the help text for 'a', which is long\n\n"));
+        assert!(r.interface.as_str().contains(
+            "DESCRIPTION:\nMakes a foo appear.\n\nThis is some extensive help text,\nwhich goes on for a while.\n\n"
+        ));
+
+        r.set_help_width(Some(20));
+        type_line(&mut r, "help foo", &mut context);
+        assert!(r.interface.as_str().ends_with(
+            "\nSUMMARY:\n  foo <a>\n\n\
+            PARAMETERS:\n  <a>\n    This is the help\n    text for 'a',\n    which is long\n\n\n\n\
+            DESCRIPTION:\nMakes a foo appear.\n\nThis is some\nextensive help text,\nwhich goes on for a\nwhile.\n\n> "
+        ));
+
+        // A word longer than the width is left whole
+        let mut out = Output::new();
+        write_wrapped(&mut out, "a verylongword b", 2, 8);
+        assert_eq!(out.as_str(), "  a\n  verylongword\n  b\n");
+    }
 }
@@ -1,16 +1,21 @@
 //! The Menu Manager looks after the menu and where we currently are within it.
 #![deny(missing_docs)]
 
-use super::{ItemType, Menu};
+use super::{Item, ItemType, Menu};
 
 /// Holds a nested tree of Menus and remembers which menu within the tree we're
 /// currently looking at.
 pub struct MenuManager<'a, I, T> {
     menu: Menu<'a, I, T>,
-    /// Maximum four levels deep
-    menu_index: [Option<usize>; 4],
+    /// Maximum four levels deep. Each level holds the index of the sub-menu
+    /// item, for generated sub-menus the index it was generated with, and
+    /// the item itself, so we can tell if it has been removed.
+    menu_index: [Option<Level<'a, I, T>>; 4],
 }
 
+/// How we got into one of the sub-menus we are in
+type Level<'a, I, T> = (usize, Option<usize>, &'a Item<'a, I, T>);
+
 impl<'a, I, T> MenuManager<'a, I, T> {
     /// Create a new MenuManager.
     ///
@@ -40,38 +45,105 @@ impl<'a, I, T> MenuManager<'a, I, T> {
     /// item. Do not push too many items.
     pub fn push_menu(&mut self, index: usize) {
         let menu = self.get_menu(None);
-        let item = menu.items[index];
-        if !matches!(item.item_type, ItemType::Menu(_)) {
+        let item = menu.item(index);
+        if !matches!(item.map(|i| &i.item_type), Some(ItemType::Menu(_))) {
             panic!("Specified index is not a menu");
         }
 
         let pos = self.menu_index.iter_mut().find(|x| x.is_none()).unwrap();
-        pos.replace(index);
+        pos.replace((index, None, item.unwrap()));
+    }
+
+    /// Drop into a generated sub-menu.
+    ///
+    /// The index must be the index of a valid
+    /// [`ItemType::Generated`] item, and
+    /// `generated_index` must be less than its `count`. Do not push too many
+    /// items.
+    pub fn push_generated_menu(&mut self, index: usize, generated_index: usize) {
+        let menu = self.get_menu(None);
+        let item = menu.item(index);
+        if !matches!(
+            item.map(|i| &i.item_type),
+            Some(ItemType::Generated { count, .. }) if generated_index < *count
+        ) {
+            panic!("Specified index is not a generated menu");
+        }
+
+        let pos = self.menu_index.iter_mut().find(|x| x.is_none()).unwrap();
+        pos.replace((index, Some(generated_index), item.unwrap()));
+    }
+
+    /// Leave any sub-menus whose item has been removed from (or replaced in)
+    /// an [`ItemTable`](super::ItemTable) since we went into them, going back
+    /// to the deepest menu which is still there. Their exit functions are not
+    /// called.
+    ///
+    /// Returns `true` if we had to leave any.
+    pub fn pop_removed_menus(&mut self) -> bool {
+        let (_menu, valid) = self.walk(self.depth());
+        let removed = valid != self.depth();
+        for level in self.menu_index.iter_mut().skip(valid) {
+            *level = None;
+        }
+        removed
+    }
+
+    /// Get the index a generated menu was generated with.
+    ///
+    /// Returns `None` if the menu at the given `depth` is not a generated
+    /// menu.
+    pub fn generated_index(&self, depth: usize) -> Option<usize> {
+        let level = depth.checked_sub(1)?;
+        self.menu_index.get(level).copied().flatten()?.1
     }
 
     /// Get a menu.
     ///
     /// Menus are nested. If `depth` is `None`, get the current menu. Otherwise
     /// if it is `Some(i)` get the menu at depth `i`.
-    pub fn get_menu(&self, depth: Option<usize>) -> &Menu<'a, I, T> {
-        let mut menu = &self.menu;
-
-        let depth = depth.unwrap_or_else(|| self.depth());
-
-        for position in self
-            .menu_index
-            .iter()
-            .take_while(|x| x.is_some())
-            .map(|x| x.unwrap())
-            .take(depth)
-        {
-            if let ItemType::Menu(m) = menu.items[position].item_type {
-                menu = m
-            } else {
-                panic!("Selected item is not a menu");
+    ///
+    /// If the menu is, or is inside, a generated menu, its `index` is set to
+    /// the index the nearest generated menu was generated with.
+    ///
+    /// If a sub-menu's item has been removed from an
+    /// [`ItemTable`](super::ItemTable), this gives the menu it was in - see
+    /// [`MenuManager::pop_removed_menus`].
+    pub fn get_menu(&self, depth: Option<usize>) -> Menu<'a, I, T> {
+        let (menu, _depth) = self.walk(depth.unwrap_or_else(|| self.depth()));
+        menu
+    }
+
+    /// Go down into the sub-menus we are in, up to `depth` of them, stopping
+    /// early at one which has been removed. Returns the menu we got to, and
+    /// how deep it is.
+    fn walk(&self, depth: usize) -> (Menu<'a, I, T>, usize) {
+        let mut menu = self.menu.clone();
+        let mut generated_index = None;
+        let mut reached = 0;
+
+        for (position, index, entered) in self.menu_index.iter().map_while(|x| *x).take(depth) {
+            if !menu
+                .item(position)
+                .is_some_and(|item| core::ptr::eq(item, entered))
+            {
+                break;
+            }
+            match (&entered.item_type, index) {
+                (ItemType::Menu(m), None) => menu = (*m).clone(),
+                (ItemType::Generated { generator, .. }, Some(index)) => {
+                    menu = generator(index);
+                    generated_index = Some(index);
+                }
+                _ => break,
             }
+            reached += 1;
         }
 
-        menu
+        if generated_index.is_some() {
+            menu.index = generated_index;
+        }
+
+        (menu, reached)
     }
 }
@@ -0,0 +1,118 @@
+//! Translating the newlines the runner writes, for terminals which need a
+//! carriage return as well.
+#![deny(missing_docs)]
+
+/// What to write at the end of each line
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Newline {
+    /// `\n`, as the runner writes it
+    #[default]
+    Lf,
+    /// `\r\n`, which most serial terminals need
+    CrLf,
+}
+
+/// Wraps an interface, and changes each `\n` written to it into the chosen
+/// [`Newline`]. A `\r\n` which is already there is left alone.
+///
+/// Reading is passed straight through, so this can be used as the
+/// [`Runner`](super::Runner)'s interface in place of the one it wraps:
+///
+/// ```rust
+/// # use menu::newline::{Newline, Translate};
+/// # use embedded_io::Write;
+/// let mut buffer = [0u8; 16];
+/// let mut out = Translate::new(&mut buffer[..], Newline::CrLf);
+/// write!(out, "a\nb\r\n").unwrap();
+/// assert_eq!(&buffer[0..6], b"a\r\nb\r\n");
+/// ```
+#[derive(Debug)]
+pub struct Translate<W> {
+    inner: W,
+    newline: Newline,
+    /// Was the last byte written a `\r`?
+    after_cr: bool,
+}
+
+impl<W> Translate<W> {
+    /// Wrap `inner`, writing `newline` at the end of each line.
+    pub fn new(inner: W, newline: Newline) -> Self {
+        Self {
+            inner,
+            newline,
+            after_cr: false,
+        }
+    }
+
+    /// Get the interface this wraps.
+    pub fn inner(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get the interface this wraps, to use it directly.
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Give back the interface this wraps.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> embedded_io::ErrorType for Translate<W>
+where
+    W: embedded_io::ErrorType,
+{
+    type Error = W::Error;
+}
+
+impl<W> embedded_io::Write for Translate<W>
+where
+    W: embedded_io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if self.newline == Newline::Lf || buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        match buf.iter().position(|b| *b == b'\n') {
+            Some(0) => {
+                let newline: &[u8] = if self.after_cr { b"\n" } else { b"\r\n" };
+                self.inner.write_all(newline)?;
+                self.after_cr = false;
+                Ok(1)
+            }
+            end => {
+                // Write up to the next newline
+                let part = &buf[0..end.unwrap_or(buf.len())];
+                let written = self.inner.write(part)?;
+                if written > 0 {
+                    self.after_cr = part[written - 1] == b'\r';
+                }
+                Ok(written)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<W> embedded_io::Read for Translate<W>
+where
+    W: embedded_io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.inner.read(buf)
+    }
+}
+
+impl<W> embedded_io::ReadReady for Translate<W>
+where
+    W: embedded_io::ReadReady,
+{
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        self.inner.read_ready()
+    }
+}
@@ -0,0 +1,28 @@
+//! Drawing your own prompt, such as `dev01:/diag/radio [ERR]# `, in place of
+//! the usual `diagradio> `.
+//!
+//! Give the [`Runner`](super::Runner) a [`PromptFn`] with
+//! [`Runner::set_prompt`](super::Runner::set_prompt). Questions asked by
+//! commands (such as `Password: `) are still shown as they are.
+#![deny(missing_docs)]
+
+use super::Status;
+
+/// The type of function which draws the prompt. Write the prompt to `out`.
+pub type PromptFn<T> =
+    fn(out: &mut dyn core::fmt::Write, info: &PromptInfo, context: &T) -> core::fmt::Result;
+
+/// What the runner knows which might be worth showing in the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptInfo<'p> {
+    /// The sub-menus the operator is in, from the top: the label of each, and
+    /// the index it was generated with if it is a generated sub-menu. This is
+    /// empty at the root menu.
+    pub path: &'p [(&'p str, Option<usize>)],
+    /// The operator's privilege level. See
+    /// [`Runner::set_enable`](super::Runner::set_enable).
+    pub privilege: u8,
+    /// What the last command returned, or [`Status::Success`] if nothing has
+    /// been run yet
+    pub last_status: Status,
+}
@@ -0,0 +1,207 @@
+//! Running scripts - a series of command lines stored somewhere other than
+//! the keyboard, such as in flash.
+#![deny(missing_docs)]
+
+use super::{Runner, Status};
+
+/// What to do when a line of a script fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop running the script
+    #[default]
+    Stop,
+    /// Carry on with the next line
+    Continue,
+}
+
+/// Settings for running a script
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptOptions {
+    /// Print the prompt and each line before running it, as if it had been
+    /// typed in
+    pub echo: bool,
+    /// What to do when a line fails
+    pub on_error: OnError,
+}
+
+/// Describes the ways in which a script can fail. Line numbers count from 1.
+///
+/// If the script carried on after an error (see [`OnError::Continue`]), this
+/// describes the first error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError<E = core::convert::Infallible> {
+    /// A command on this line failed
+    Failed(usize),
+    /// This line did not fit in the line buffer
+    LineTooLong(usize),
+    /// This line was not valid UTF-8
+    InvalidUtf8(usize),
+    /// This line was stopped by an [`Interrupt`](crate::interrupt::Interrupt).
+    /// The script always stops when this happens.
+    Interrupted(usize),
+    /// Reading the script failed
+    Read(E),
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for ScriptError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+#[rustversion::since(1.81)]
+impl<E: core::fmt::Debug> core::error::Error for ScriptError<E> {}
+
+impl<I, T, B: ?Sized> Runner<'_, I, T, B>
+where
+    I: embedded_io::Write,
+{
+    /// Run each line of `script` through the menu, as if it had been typed
+    /// in.
+    ///
+    /// Blank lines, and lines starting with `#`, are skipped.
+    ///
+    /// A command which returns [`Status::Pending`] is polled until it
+    /// finishes, and `watch` runs until a key is pressed. A command which
+    /// asks a question fails, as scripts can't answer them - give `--yes` to
+    /// a command which asks to be confirmed.
+    pub fn run_script(
+        &mut self,
+        script: &str,
+        options: ScriptOptions,
+        context: &mut T,
+    ) -> Result<(), ScriptError> {
+        let mut first_error = None;
+        self.inner.take_interrupt();
+        for (idx, line) in script.lines().enumerate() {
+            if let Err(e) = self.run_script_line(line, idx + 1, options, context) {
+                let stop = options.on_error == OnError::Stop || is_interrupted(&e);
+                first_error.get_or_insert(e);
+                if stop {
+                    break;
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Read a script from `reader`, and run each line through the menu, as
+    /// if it had been typed in.
+    ///
+    /// Each line is read into `line_buffer` before it is run, so it must be
+    /// long enough for the longest line (comments can be longer). Blank
+    /// lines, and lines starting with `#`, are skipped. Commands which keep
+    /// running or ask questions are handled as in [`Runner::run_script`].
+    ///
+    /// If reading fails, the script stops and [`ScriptError::Read`] is
+    /// returned, even if a line had already failed.
+    pub fn run_script_from<R>(
+        &mut self,
+        reader: &mut R,
+        line_buffer: &mut [u8],
+        options: ScriptOptions,
+        context: &mut T,
+    ) -> Result<(), ScriptError<R::Error>>
+    where
+        R: embedded_io::Read,
+    {
+        let mut first_error = None;
+        let mut line_number = 1;
+        let mut used = 0;
+        let mut too_long = false;
+        self.inner.take_interrupt();
+        loop {
+            let mut byte = [0u8];
+            let at_end = match reader.read(&mut byte) {
+                Ok(0) => true,
+                Ok(_) if byte[0] == b'\n' => false,
+                Ok(_) => {
+                    if used < line_buffer.len() {
+                        line_buffer[used] = byte[0];
+                        used += 1;
+                    } else {
+                        too_long = true;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(ScriptError::Read(e)),
+            };
+            let is_comment = line_buffer[0..used]
+                .iter()
+                .find(|b| !b.is_ascii_whitespace())
+                == Some(&b'#');
+            let result = if too_long && !is_comment {
+                Err(ScriptError::LineTooLong(line_number))
+            } else if too_long {
+                Ok(())
+            } else {
+                match core::str::from_utf8(&line_buffer[0..used]) {
+                    Ok(line) => self.run_script_line(line, line_number, options, context),
+                    Err(_) => Err(ScriptError::InvalidUtf8(line_number)),
+                }
+            };
+            if let Err(e) = result {
+                let stop = options.on_error == OnError::Stop || is_interrupted(&e);
+                first_error.get_or_insert(e);
+                if stop {
+                    break;
+                }
+            }
+            if at_end {
+                break;
+            }
+            line_number += 1;
+            used = 0;
+            too_long = false;
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Run one line of a script.
+    fn run_script_line<E>(
+        &mut self,
+        line: &str,
+        line_number: usize,
+        options: ScriptOptions,
+        context: &mut T,
+    ) -> Result<(), ScriptError<E>> {
+        let line = line.trim_end_matches('\r');
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(());
+        }
+        if options.echo {
+            self.inner.prompt(&mut self.interface, context, false);
+            writeln!(self.interface, "{}", line).unwrap();
+        }
+        let mut status = self.inner.run_line(&mut self.interface, context, line);
+        // Wait for a command which is still running
+        while self.inner.is_running() && !self.inner.interrupt_requested() {
+            status = self.inner.poll(&mut self.interface, context);
+        }
+        if self.inner.take_interrupt() {
+            writeln!(self.interface, "^C").unwrap();
+            self.inner.cancel(&mut self.interface, context);
+            return Err(ScriptError::Interrupted(line_number));
+        }
+        match status {
+            Status::Success => Ok(()),
+            Status::Failure => Err(ScriptError::Failed(line_number)),
+            Status::Ask(_) => {
+                self.inner.cancel(&mut self.interface, context);
+                self.inner.print_error(
+                    &mut self.interface,
+                    format_args!("Error: Scripts cannot answer questions"),
+                );
+                Err(ScriptError::Failed(line_number))
+            }
+            // We waited above for anything which was still running
+            Status::Pending => unreachable!(),
+        }
+    }
+}
+
+/// Did this error come from an interrupt?
+fn is_interrupted<E>(error: &ScriptError<E>) -> bool {
+    matches!(error, ScriptError::Interrupted(_))
+}
@@ -0,0 +1,176 @@
+//! Logging in to password-protected menus, and privilege levels.
+#![deny(missing_docs)]
+
+/// The type of function we call to check a password. Return `true` to let
+/// the operator in.
+pub type VerifyFn<T> = fn(context: &mut T, password: &str) -> bool;
+
+/// The type of function we call to check the password for a privilege level.
+/// Return `true` to give the operator that level.
+pub type EnableFn<T> = fn(context: &mut T, level: u8, password: &str) -> bool;
+
+/// A password check on the way into a menu. See [`Menu::gate`](super::Menu::gate).
+///
+/// Gates are told apart by their `name`. Once the operator has given the
+/// right password they can enter menus behind a gate with that name again
+/// without it, until they use the `logout` command. Menus behind a gate with
+/// a different name still ask for that gate's password. Each name has its
+/// own count of wrong passwords, and is locked on its own.
+pub struct Gate<T> {
+    /// Tells this gate apart from the others. Menus behind gates with the
+    /// same name share one login.
+    pub name: &'static str,
+    /// Shown when asking for the password, e.g. `"Password: "`
+    pub prompt: &'static str,
+    /// Checks the password
+    pub verify: VerifyFn<T>,
+    /// How many wrong passwords in a row before this gate is locked
+    pub max_failures: u8,
+    /// How long this gate stays locked for, in milliseconds. This needs a
+    /// clock - see [`Runner::set_clock`](super::Runner::set_clock). Without
+    /// one, the gate never locks.
+    pub lockout_ms: u32,
+}
+
+/// How many gates we can keep track of at once. When another one comes
+/// along, we forget a gate which is not locked, if we can - preferably one
+/// which the operator has not logged in to.
+const MAX_GATES: usize = 4;
+
+/// What we know about one gate
+#[derive(Debug, Clone, Copy)]
+struct GateState {
+    /// The gate's [`Gate::name`]
+    name: &'static str,
+    /// Has the operator given the password?
+    passed: bool,
+    /// How many wrong passwords have been given in a row
+    failures: u8,
+    /// When the gate was locked, and for how long
+    locked: Option<(u32, u32)>,
+}
+
+/// Who is using the menu.
+#[derive(Debug, Default)]
+pub(crate) struct Session {
+    /// The gates we know about, oldest first
+    gates: [Option<GateState>; MAX_GATES],
+    /// The privilege level the operator has
+    pub(crate) level: u8,
+    /// How long the operator can be idle before being logged out
+    pub(crate) idle_timeout_ms: Option<u32>,
+    /// When the operator last did something
+    pub(crate) last_active_ms: Option<u32>,
+}
+
+impl Session {
+    /// Is this gate locked at this time? Forgets an old lock which has run
+    /// out.
+    pub(crate) fn is_locked<T>(&mut self, gate: &Gate<T>, now_ms: Option<u32>) -> bool {
+        let Some(state) = self.find(gate) else {
+            return false;
+        };
+        let (Some((locked_at, lockout_ms)), Some(now_ms)) = (state.locked, now_ms) else {
+            return false;
+        };
+        if now_ms.wrapping_sub(locked_at) < lockout_ms {
+            return true;
+        }
+        state.locked = None;
+        state.failures = 0;
+        false
+    }
+
+    /// Remember a wrong password for this gate, and lock it if there have
+    /// been too many.
+    pub(crate) fn failed<T>(&mut self, gate: &Gate<T>, now_ms: Option<u32>) {
+        let state = self.find_or_add(gate);
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= gate.max_failures {
+            if let Some(now_ms) = now_ms {
+                state.locked = Some((now_ms, gate.lockout_ms));
+            }
+        }
+    }
+
+    /// Is there anything to lose when the operator is logged out?
+    pub(crate) fn is_elevated(&self) -> bool {
+        self.logged_in() || self.level != 0
+    }
+
+    /// Has the operator given the password for any gate?
+    pub(crate) fn logged_in(&self) -> bool {
+        self.gates.iter().flatten().any(|state| state.passed)
+    }
+
+    /// Has the operator given the password for this gate?
+    pub(crate) fn has_passed<T>(&self, gate: &Gate<T>) -> bool {
+        self.gates
+            .iter()
+            .flatten()
+            .any(|state| state.name == gate.name && state.passed)
+    }
+
+    /// Remember a right password for this gate.
+    pub(crate) fn succeeded<T>(&mut self, gate: &Gate<T>) {
+        let state = self.find_or_add(gate);
+        state.passed = true;
+        state.failures = 0;
+    }
+
+    /// Forget every gate the operator has given the password for. Wrong
+    /// passwords, and locks, are still remembered.
+    pub(crate) fn log_out(&mut self) {
+        let mut kept = [None; MAX_GATES];
+        let failing = self
+            .gates
+            .iter()
+            .flatten()
+            .filter(|state| state.failures != 0);
+        for (slot, state) in kept.iter_mut().zip(failing) {
+            *slot = Some(GateState {
+                passed: false,
+                ..*state
+            });
+        }
+        self.gates = kept;
+    }
+
+    /// Get what we know about this gate, if anything.
+    fn find<T>(&mut self, gate: &Gate<T>) -> Option<&mut GateState> {
+        self.gates
+            .iter_mut()
+            .flatten()
+            .find(|state| state.name == gate.name)
+    }
+
+    /// Get what we know about this gate, making room for it if it is new.
+    fn find_or_add<T>(&mut self, gate: &Gate<T>) -> &mut GateState {
+        let slot = match self
+            .gates
+            .iter()
+            .position(|slot| slot.is_none_or(|state| state.name == gate.name))
+        {
+            Some(slot) => slot,
+            None => {
+                // Forget one to make room, oldest first
+                let unused = |passed: bool| {
+                    self.gates
+                        .iter()
+                        .flatten()
+                        .position(|state| state.locked.is_none() && state.passed == passed)
+                };
+                let victim = unused(false).or_else(|| unused(true)).unwrap_or(0);
+                self.gates[victim..].rotate_left(1);
+                self.gates[MAX_GATES - 1] = None;
+                MAX_GATES - 1
+            }
+        };
+        self.gates[slot].get_or_insert(GateState {
+            name: gate.name,
+            passed: false,
+            failures: 0,
+            locked: None,
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! A fixed-capacity store of named strings, held in a buffer supplied by the
+//! application. Used for aliases and variables.
+
+use super::Error;
+
+/// Each entry is the length of the name (one byte), the length of the value
+/// (two bytes, little-endian), the name, then the value.
+const HEADER_LEN: usize = 3;
+
+/// Named strings, packed one after the other at the start of a buffer. The
+/// rest of the buffer is free space, which can be used as scratch space.
+pub(crate) struct Store<'a> {
+    buffer: &'a mut [u8],
+    used: usize,
+}
+
+/// A read-only view of the entries in a [`Store`].
+#[derive(Clone, Copy)]
+pub(crate) struct Entries<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Store<'a> {
+    /// Create an empty store, which keeps its entries in `buffer`.
+    pub(crate) fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, used: 0 }
+    }
+
+    /// Add an entry, replacing any existing entry with the same name.
+    ///
+    /// Returns `Err(Error::TableFull)` if there is not enough room left, or
+    /// the name or value is too long to store, in which case any existing
+    /// entry is left unchanged.
+    pub(crate) fn define(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let existing = self
+            .entries()
+            .find(name)
+            .map_or(0, |(start, end)| end - start);
+        if HEADER_LEN + name.len() + value.len() > self.buffer.len() - self.used + existing
+            || u8::try_from(name.len()).is_err()
+            || u16::try_from(value.len()).is_err()
+        {
+            return Err(Error::TableFull);
+        }
+        // There's room once the old one is gone
+        self.remove(name);
+        self.define_with(name, |_, space| {
+            let dest = space.get_mut(0..value.len())?;
+            dest.copy_from_slice(value.as_bytes());
+            Some(value.len())
+        })
+    }
+
+    /// Add an entry, replacing any existing entry with the same name. The
+    /// value is written by `write`, which is given the free space and returns
+    /// how many bytes it used, or `None` if it ran out of room.
+    ///
+    /// While `write` runs the existing entries are still there, so the new
+    /// value can be made from them, but this means there must be room for
+    /// both the old entry and the new one.
+    pub(crate) fn define_with<F>(&mut self, name: &str, write: F) -> Result<(), Error>
+    where
+        F: FnOnce(Entries<'_>, &mut [u8]) -> Option<usize>,
+    {
+        let name_len = u8::try_from(name.len()).map_err(|_| Error::TableFull)?;
+        let (entries, free) = self.split_free();
+        let header = free
+            .get_mut(0..HEADER_LEN + name.len())
+            .ok_or(Error::TableFull)?;
+        header[HEADER_LEN..].copy_from_slice(name.as_bytes());
+        let value_len = write(entries, &mut free[HEADER_LEN + name.len()..])
+            .and_then(|len| u16::try_from(len).ok())
+            .ok_or(Error::TableFull)?;
+        free[0] = name_len;
+        free[1..HEADER_LEN].copy_from_slice(&value_len.to_le_bytes());
+        // The new entry goes in, then the old one (if any) comes out
+        let old = self.entries().find(name);
+        self.used += HEADER_LEN + name.len() + usize::from(value_len);
+        if let Some((start, end)) = old {
+            self.buffer.copy_within(end..self.used, start);
+            self.used -= end - start;
+        }
+        Ok(())
+    }
+
+    /// Remove an entry. Returns `false` if there was no such entry.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let Some((start, end)) = self.entries().find(name) else {
+            return false;
+        };
+        self.buffer.copy_within(end..self.used, start);
+        self.used -= end - start;
+        true
+    }
+
+    /// Get a read-only view of the entries.
+    pub(crate) fn entries(&self) -> Entries<'_> {
+        Entries {
+            buffer: &self.buffer[0..self.used],
+        }
+    }
+
+    /// Split the buffer into the entries and the free space after them.
+    pub(crate) fn split_free(&mut self) -> (Entries<'_>, &mut [u8]) {
+        let (buffer, free) = self.buffer.split_at_mut(self.used);
+        (Entries { buffer }, free)
+    }
+}
+
+impl<'a> Entries<'a> {
+    /// Get the value of an entry.
+    pub(crate) fn get(self, name: &str) -> Option<&'a str> {
+        self.get_matching(|entry| entry == name)
+    }
+
+    /// Get the value of the first entry whose name `matches`.
+    pub(crate) fn get_matching<F>(self, matches: F) -> Option<&'a str>
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.iter()
+            .find(|(entry, _)| matches(entry))
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate through the entries, as `(name, value)` pairs.
+    pub(crate) fn iter(self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.ranges().map(move |(start, _)| self.entry(start))
+    }
+
+    /// Find the start and end of the entry with this name.
+    fn find(self, name: &str) -> Option<(usize, usize)> {
+        self.ranges()
+            .find(|(start, _)| self.entry(*start).0 == name)
+    }
+
+    /// Iterate through the start and end of every entry.
+    fn ranges(self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut start = 0;
+        core::iter::from_fn(move || {
+            if start >= self.buffer.len() {
+                return None;
+            }
+            let end = start + entry_len(&self.buffer[start..]);
+            let result = (start, end);
+            start = end;
+            Some(result)
+        })
+    }
+
+    /// Get the name and value of the entry which starts here.
+    fn entry(self, start: usize) -> (&'a str, &'a str) {
+        let entry = &self.buffer[start..start + entry_len(&self.buffer[start..])];
+        let (name, value) = entry[HEADER_LEN..].split_at(usize::from(entry[0]));
+        // Both were copied in from a `&str`
+        (
+            core::str::from_utf8(name).unwrap(),
+            core::str::from_utf8(value).unwrap(),
+        )
+    }
+}
+
+/// How long is the entry at the start of this buffer?
+fn entry_len(buffer: &[u8]) -> usize {
+    let name_len = usize::from(buffer[0]);
+    let value_len = usize::from(u16::from_le_bytes([buffer[1], buffer[2]]));
+    HEADER_LEN + name_len + value_len
+}
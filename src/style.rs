@@ -0,0 +1,58 @@
+//! Colours and other styling for the prompt, help and error messages, using
+//! ANSI escape codes.
+//!
+//! Only available with the `style` feature.
+#![deny(missing_docs)]
+
+/// Turns off any styling
+pub const RESET: &str = "\x1b[0m";
+
+/// How to style each kind of output. Each field is the ANSI escape code (or
+/// codes) to write before that kind of output - [`RESET`] is written after.
+///
+/// Make your own, or start from [`Theme::DEFAULT`]:
+///
+/// ```rust
+/// # use menu::style::Theme;
+/// let theme = Theme {
+///     prompt: "\x1b[1;36m",
+///     ..Theme::DEFAULT
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// The prompt, such as `sub> `
+    pub prompt: &'static str,
+    /// Headings in `help`, such as `SUMMARY:`
+    pub heading: &'static str,
+    /// Error messages
+    pub error: &'static str,
+}
+
+impl Theme {
+    /// A green prompt, bold headings and red errors
+    pub const DEFAULT: Theme = Theme {
+        prompt: "\x1b[1;32m",
+        heading: "\x1b[1m",
+        error: "\x1b[31m",
+    };
+
+    /// Use this theme only if the output looks like it will understand it.
+    ///
+    /// `term` is the value of the `TERM` environment variable, if there is
+    /// one, and `is_terminal` says whether the output goes to a terminal,
+    /// rather than being captured to a file or log. Returns `None` for a
+    /// `dumb` (or unknown) terminal, or when the output isn't a terminal.
+    pub fn if_supported(self, term: Option<&str>, is_terminal: bool) -> Option<Theme> {
+        match term {
+            Some(term) if is_terminal && !term.is_empty() && term != "dumb" => Some(self),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -0,0 +1,147 @@
+//! Variables set by the operator at run-time, such as `set addr 0x50`, which
+//! are substituted into later commands, as in `i2c read $addr 4`.
+//!
+//! Only available with the `variables` feature.
+#![deny(missing_docs)]
+
+use super::{
+    store::{Entries, Store},
+    Error, Status,
+};
+
+/// A fixed-capacity store of variables, held in a buffer you supply.
+///
+/// Each variable takes up three bytes, plus the length of its name and its
+/// value. Any space which is left over is used to hold each command line
+/// after the variables have been substituted into it.
+pub struct Variables<'a> {
+    store: Store<'a>,
+}
+
+/// Why a line could not be expanded
+pub(crate) enum ExpandError<'s> {
+    /// There is no variable with this name
+    Unknown(&'s str),
+    /// There was not enough free space for the expanded line
+    NoRoom,
+}
+
+impl<'a> Variables<'a> {
+    /// Create an empty variable store, which keeps its variables in `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            store: Store::new(buffer),
+        }
+    }
+
+    /// Set a variable, replacing any existing value.
+    ///
+    /// Only names made of ASCII letters, digits and `_` can be substituted.
+    /// Returns `Err(Error::TableFull)` if there is not enough room left, or
+    /// the name is over 255 bytes or the value over 65535 bytes, in which
+    /// case any existing value is left unchanged.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        self.store.define(name, value)
+    }
+
+    /// Remove a variable. Returns `false` if there was no such variable.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.store.remove(name)
+    }
+
+    /// Get the value of a variable.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.store.entries().get(name)
+    }
+
+    /// Iterate through the variables, as `(name, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.store.entries().iter()
+    }
+
+    /// Set a variable, substituting other variables into its value first.
+    pub(crate) fn set_expanded<'s>(
+        &mut self,
+        name: &str,
+        value: &'s str,
+        status: Status,
+    ) -> Result<(), ExpandError<'s>> {
+        let mut result = Ok(());
+        let defined = self.store.define_with(name, |entries, space| {
+            expand_into(entries, value, status, space)
+                .map_err(|e| result = Err(e))
+                .ok()
+        });
+        result?;
+        defined.map_err(|_| ExpandError::NoRoom)
+    }
+
+    /// Substitute variables into `line`.
+    ///
+    /// `$name` is replaced with the value of that variable, `$?` with `0` if
+    /// the last command worked or `1` if it failed, and `$$` with `$`.
+    pub(crate) fn expand<'s>(
+        &'s mut self,
+        line: &'s str,
+        status: Status,
+    ) -> Result<&'s str, ExpandError<'s>> {
+        if !line.contains('$') {
+            return Ok(line);
+        }
+        let (entries, space) = self.store.split_free();
+        let len = expand_into(entries, line, status, space)?;
+        // We only ever copy in whole strings
+        Ok(core::str::from_utf8(&space[0..len]).unwrap())
+    }
+}
+
+/// Can this be the name of a variable?
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(is_name_byte)
+}
+
+/// Can this byte be part of a variable name?
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// Write `text` into `space`, with variables substituted in. Returns how many
+/// bytes were written.
+fn expand_into<'s>(
+    entries: Entries<'_>,
+    text: &'s str,
+    status: Status,
+    space: &mut [u8],
+) -> Result<usize, ExpandError<'s>> {
+    let mut used = 0;
+    let mut push = |s: &str| {
+        let dest = space
+            .get_mut(used..used + s.len())
+            .ok_or(ExpandError::NoRoom)?;
+        dest.copy_from_slice(s.as_bytes());
+        used += s.len();
+        Ok(())
+    };
+    let mut rest = text;
+    while let Some(pos) = rest.find('$') {
+        push(&rest[0..pos])?;
+        let after = &rest[pos + 1..];
+        let name_len = after.bytes().take_while(|b| is_name_byte(*b)).count();
+        rest = if let Some(after) = after.strip_prefix('?') {
+            push(if status == Status::Success { "0" } else { "1" })?;
+            after
+        } else if let Some(after) = after.strip_prefix('$') {
+            push("$")?;
+            after
+        } else if name_len == 0 {
+            push("$")?;
+            after
+        } else {
+            let name = &after[0..name_len];
+            push(entries.get(name).ok_or(ExpandError::Unknown(name))?)?;
+            &after[name_len..]
+        };
+    }
+    push(rest)?;
+    Ok(used)
+}
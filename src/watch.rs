@@ -0,0 +1,63 @@
+//! Support for the `watch` command, which runs a command over and over until
+//! a key is pressed.
+#![deny(missing_docs)]
+
+use super::Runner;
+
+/// Something which can tell the time, such as a hardware timer. Give one to
+/// the runner with [`Runner::set_clock`], or with [`Runner::set_time_source`]
+/// to enable `watch` as well.
+pub trait TimeSource {
+    /// Get the current time in milliseconds.
+    ///
+    /// It doesn't matter when the count started, and it is fine for it to
+    /// wrap around.
+    fn now_ms(&mut self) -> u32;
+}
+
+/// What the runner needs to run `watch`, apart from the clock.
+pub(crate) struct Watch<I> {
+    /// Checks for (and eats) a key press on the interface
+    pub(crate) key_pressed: fn(&mut I) -> bool,
+}
+
+impl<'a, I, T, B: ?Sized> Runner<'a, I, T, B>
+where
+    I: embedded_io::Write + embedded_io::Read + embedded_io::ReadReady,
+{
+    /// Give the runner a clock, as [`Runner::set_clock`] does, and enable
+    /// the `watch` command.
+    ///
+    /// `watch 500 sensor read` runs `sensor read` every 500 milliseconds,
+    /// until a key is pressed on the interface.
+    ///
+    /// Like a command which returns [`Status::Pending`](super::Status::Pending),
+    /// `watch` is carried on by [`Runner::poll`], which runs the command
+    /// again when it is time. Any key given to [`Runner::input_byte`] stops
+    /// it, and so does a key which `poll` finds waiting on the interface.
+    pub fn set_time_source(&mut self, clock: &'a mut dyn TimeSource) {
+        self.set_clock(clock);
+        self.inner.watch = Some(Watch {
+            key_pressed: key_pressed::<I>,
+        });
+    }
+}
+
+/// Has a key been pressed? If so, the key is thrown away.
+///
+/// If the interface reports an error we say yes, so `watch` doesn't run
+/// forever.
+fn key_pressed<I>(interface: &mut I) -> bool
+where
+    I: embedded_io::Read + embedded_io::ReadReady,
+{
+    match interface.read_ready() {
+        Ok(false) => false,
+        Ok(true) => {
+            let mut byte = [0u8];
+            let _ = interface.read(&mut byte);
+            true
+        }
+        Err(_) => true,
+    }
+}